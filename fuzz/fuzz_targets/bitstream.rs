@@ -0,0 +1,44 @@
+//! Reads a backward bitstream with arbitrary widths and compares with reading
+//! it bit by bit. The first byte is the number of reads, followed by their
+//! widths, the remaining bytes are the stream.
+//!
+//! `cargo +nightly fuzz run bitstream`
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use zstd::fuzzing::{Bitstream, MAX_READ_BITS};
+
+fuzz_target!(|data: &[u8]| {
+    let Some((&number_of_reads, data)) = data.split_first() else {
+        return;
+    };
+    let (widths, stream) = data.split_at(data.len().min(number_of_reads as usize));
+
+    let Ok(mut bs) = Bitstream::new(stream) else {
+        assert!(stream.last().is_none_or(|&byte| byte == 0));
+        return;
+    };
+
+    // Bits are numbered from the one following the padding marker, bits past
+    // the start of the stream are zeroes.
+    let total_bits = stream.len() * 8 - 1 - stream[stream.len() - 1].leading_zeros() as usize;
+    let bit = |position: usize| -> u64 {
+        if position >= total_bits {
+            return 0;
+        }
+        let index = total_bits - 1 - position;
+        (stream[index / 8] >> (index % 8)) as u64 & 1
+    };
+
+    let mut position = 0;
+    for width in widths {
+        let n = width % (MAX_READ_BITS + 1);
+        let expected = (position..position + n as usize).fold(0, |v, p| (v << 1) | bit(p));
+        assert_eq!(bs.peek_bits(n), expected);
+        assert_eq!(bs.get_bits(n), expected);
+
+        position += n as usize;
+        assert_eq!(bs.bits_remaining(), total_bits as isize - position as isize);
+    }
+});
@@ -0,0 +1,15 @@
+//! Decodes arbitrary input, which must fail with an error instead of
+//! panicking when it is not a valid Zstandard stream.
+//!
+//! `cargo +nightly fuzz run decode`
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use zstd::Zstd;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(zstd) = Zstd::from_bytes(data.to_vec()) {
+        let _ = zstd.decode();
+    }
+});
@@ -0,0 +1,73 @@
+//! Compares this decoder with the reference `zstd` binary, which must be in
+//! the `PATH`. Spawning it for every input is slow, so this target is meant
+//! to be run locally rather than continuously.
+//!
+//! The input is compressed by the reference encoder at a level picked from
+//! its first byte, and must decode back to itself. It is also decoded as
+//! is, and whenever the reference decoder accepts it this decoder must
+//! produce the same output.
+//!
+//! `cargo +nightly fuzz run differential`
+
+#![no_main]
+
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+use libfuzzer_sys::fuzz_target;
+use zstd::Zstd;
+
+const MAGIC_NUMBER: u32 = 0xFD2FB528;
+const SKIPPABLE_MAGIC_NUMBER: u32 = 0x184D2A50;
+
+fn reference_zstd(args: &[&str], input: &[u8]) -> Option<Vec<u8>> {
+    let mut child = Command::new("zstd")
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .expect("the zstd binary should be in the PATH");
+
+    let mut stdin = child.stdin.take().unwrap();
+    let input = input.to_vec();
+    let writer = std::thread::spawn(move || {
+        let _ = stdin.write_all(&input);
+    });
+    let output = child.wait_with_output().unwrap();
+    writer.join().unwrap();
+
+    output.status.success().then_some(output.stdout)
+}
+
+fuzz_target!(|data: &[u8]| {
+    let Some((&selector, content)) = data.split_first() else {
+        return;
+    };
+
+    let level = format!("-{}", 1 + selector % 19);
+    let checksum = if selector & 0x80 != 0 {
+        "--check"
+    } else {
+        "--no-check"
+    };
+    let compressed = reference_zstd(&["-c", "-q", &level, checksum, "-"], content)
+        .expect("the reference encoder should accept any input");
+    let decoded = Zstd::from_bytes(compressed)
+        .and_then(Zstd::decode)
+        .expect("frames from the reference encoder should decode");
+    assert_eq!(decoded, content);
+
+    // Other formats and legacy frames accepted by the reference decoder are
+    // out of scope.
+    let Some(magic_number) = data.first_chunk().copied().map(u32::from_le_bytes) else {
+        return;
+    };
+    if magic_number != MAGIC_NUMBER && magic_number & 0xFFFFFFF0 != SKIPPABLE_MAGIC_NUMBER {
+        return;
+    }
+    if let Some(expected) = reference_zstd(&["-d", "-c", "-q", "--format=zstd", "-"], data) {
+        let actual = Zstd::from_bytes(data.to_vec()).and_then(Zstd::decode);
+        assert_eq!(actual.ok(), Some(expected));
+    }
+});
@@ -0,0 +1,52 @@
+//! Builds FSE decoding tables from arbitrary normalized distributions. The
+//! first byte selects the accuracy log from 5 to 9, each following byte is
+//! the probability of the next symbol, 255 meaning "less than 1". The last
+//! symbol takes what remains of the table.
+//!
+//! `cargo +nightly fuzz run fse_distribution`
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use zstd::fuzzing::{FseDecoder, FseDecodingTable};
+
+fuzz_target!(|data: &[u8]| {
+    let Some((&selector, probabilities)) = data.split_first() else {
+        return;
+    };
+    let accuracy_log = 5 + selector % 5;
+    let table_size = 1i16 << accuracy_log;
+
+    let mut remaining = table_size;
+    let mut distribution = vec![];
+    for &probability in probabilities.iter().take(254) {
+        if remaining == 0 {
+            break;
+        }
+        let probability = match probability {
+            255 => -1,
+            p => (p as i16).min(remaining),
+        };
+        remaining -= probability.abs();
+        distribution.push(probability);
+    }
+    if remaining > 0 {
+        distribution.push(remaining);
+    }
+
+    let table = FseDecodingTable::from_distribution(&distribution, accuracy_log).unwrap();
+    assert_eq!(table.accuracy_log(), accuracy_log);
+
+    let mut decoder = FseDecoder::new(table, 0);
+    let mut cells = vec![0i16; distribution.len()];
+    for state in 0..table_size as u16 {
+        decoder.set_state(state);
+        cells[decoder.symbol() as usize] += 1;
+        // The next state must stay inside the table whatever bits are read.
+        assert!(decoder.baseline() as usize + (1 << decoder.num_bits()) <= table_size as usize);
+    }
+
+    for (probability, cells) in distribution.iter().zip(cells) {
+        assert_eq!(probability.abs(), cells);
+    }
+});
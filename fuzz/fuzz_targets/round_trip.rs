@@ -0,0 +1,22 @@
+//! Compresses arbitrary input with this encoder, which must decode back to
+//! itself. The first byte selects the compression level.
+//!
+//! `cargo +nightly fuzz run round_trip`
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use zstd::{Zstd, MAX_LEVEL};
+
+fuzz_target!(|data: &[u8]| {
+    let Some((&selector, data)) = data.split_first() else {
+        return;
+    };
+    let level = selector % (MAX_LEVEL + 1);
+    let compressed =
+        Zstd::encode_with_level(data.to_vec(), level).expect("any input should be encoded");
+    let decoded = Zstd::from_bytes(compressed)
+        .and_then(Zstd::decode)
+        .expect("frames from the encoder should decode");
+    assert_eq!(decoded, data);
+});
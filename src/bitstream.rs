@@ -1,139 +1,329 @@
-use log::debug;
+use std::cmp::Ordering;
 
-pub struct Bitstream {
-    bytes: Vec<u8>,
-    current_bit: usize,
+use crate::{Error, Res};
+
+/// Maximum number of bits read at once, the container always holds at least
+/// that many bits after a refill.
+pub const MAX_READ_BITS: u8 = 56;
+
+/// Backward bitstream, read from the last byte to the first one and from the
+/// most significant bit to the least significant one of each byte, after the
+/// padding marker ending the stream.
+pub struct Bitstream<'a> {
+    /// Bytes not loaded in the container yet.
+    bytes: &'a [u8],
+    /// The next bit to read is bit `bits_available - 1`, the bits above it
+    /// were already consumed.
+    container: u64,
+    bits_available: u8,
+    /// Number of bits read past the start of the stream, as zeroes.
+    overconsumed: usize,
 }
 
-impl Bitstream {
-    pub fn new(bytes: Vec<u8>) -> Self {
-        Self {
-            bytes,
-            current_bit: 0,
+impl<'a> Bitstream<'a> {
+    pub fn new(bytes: &'a [u8]) -> Res<Self> {
+        let Some((&last_byte, bytes)) = bytes.split_last() else {
+            return Err(Error::CorruptBitstream("empty bitstream"));
+        };
+        if last_byte == 0 {
+            return Err(Error::CorruptBitstream(
+                "bitstream doesn't end with a padding marker",
+            ));
         }
+
+        Ok(Self {
+            bytes,
+            container: last_byte as u64,
+            bits_available: 7 - last_byte.leading_zeros() as u8,
+            overconsumed: 0,
+        })
+    }
+
+    pub fn get_bits(&mut self, n: u8) -> u64 {
+        let result = self.peek_bits(n);
+        self.consume_bits(n);
+        result
     }
 
-    pub fn get_bits(&mut self, mut n: u8) -> u64 {
+    /// Reads the next `n` bits without consuming them. Bits past the start of
+    /// the stream are read as zeroes.
+    pub fn peek_bits(&mut self, n: u8) -> u64 {
+        debug_assert!(n <= MAX_READ_BITS, "too many bits to read: {}", n);
         if n == 0 {
             return 0;
         }
 
-        if n > 64 {
-            panic!("too many bytes to read: {} bytes", n);
+        if self.bits_available < n {
+            self.refill();
+            if self.bits_available < n {
+                return (self.container << (n - self.bits_available)) & Self::mask(n);
+            }
         }
+        (self.container >> (self.bits_available - n)) & Self::mask(n)
+    }
 
-        if self.current_bit == 0 {
-            let skipping_bits = self.bytes[0].leading_zeros() + 1;
-            debug!("skipping {} bits", skipping_bits);
-            self.current_bit += skipping_bits as usize;
+    pub fn consume_bits(&mut self, n: u8) {
+        if self.bits_available < n {
+            self.refill();
+            if self.bits_available < n {
+                self.overconsumed += (n - self.bits_available) as usize;
+                self.bits_available = 0;
+                return;
+            }
         }
+        self.bits_available -= n;
+    }
 
-        let mut i = self.current_bit / 8;
-        let used_bits_len = self.current_bit as u8 % 8;
-        self.current_bit += n as usize;
-        let unused_bits_len = 8 - used_bits_len;
+    /// Number of bits left before the start of the stream, negative if more
+    /// bits were consumed than the stream contains.
+    pub fn bits_remaining(&self) -> isize {
+        let remaining = self.bytes.len() * 8 + self.bits_available as usize;
+        remaining as isize - self.overconsumed as isize
+    }
 
-        if n <= unused_bits_len {
-            return Self::get_bits_range(self.bytes[i], used_bits_len, n) as u64;
+    /// Checks that the stream was consumed exactly, up to its first bit.
+    pub fn check_fully_consumed(&self) -> Res<()> {
+        match self.bits_remaining().cmp(&0) {
+            Ordering::Equal => Ok(()),
+            Ordering::Less => Err(Error::CorruptBitstream("bitstream was overconsumed")),
+            Ordering::Greater => Err(Error::CorruptBitstream("bitstream was not fully consumed")),
         }
+    }
 
-        let mut result = Self::get_bits_range(self.bytes[i], used_bits_len, unused_bits_len) as u64;
-        n -= unused_bits_len;
-        while n > 8 {
-            result = (result << 8) + self.bytes[i] as u64;
-            i += 1;
-            n -= 8;
+    /// Loads as many whole bytes as fit in the container, which then holds
+    /// at least `MAX_READ_BITS` bits unless the start of the stream is
+    /// reached.
+    fn refill(&mut self) {
+        let len = ((63 - self.bits_available) / 8) as usize;
+        if len == 0 {
+            return;
         }
-        let last_bits = Self::get_bits_range(self.bytes[i + 1], 0, n) as u64;
-        (result << n) + last_bits
+
+        let (loaded, len) = match self.bytes.last_chunk::<8>() {
+            Some(word) => (u64::from_le_bytes(*word) >> (64 - 8 * len), len),
+            None => {
+                let len = len.min(self.bytes.len());
+                let mut word = [0; 8];
+                word[..len].copy_from_slice(&self.bytes[self.bytes.len() - len..]);
+                (u64::from_le_bytes(word), len)
+            }
+        };
+        self.bytes = &self.bytes[..self.bytes.len() - len];
+        self.bits_available += 8 * len as u8;
+        self.container = (self.container << (8 * len)) | loaded;
     }
 
-    fn get_bits_range(byte: u8, start: u8, n: u8) -> u8 {
-        if start == 0 && n == 8 {
-            return byte;
-        }
-        if start > 8 || n > 8 {
-            panic!("start or n too big");
+    fn mask(n: u8) -> u64 {
+        (1 << n) - 1
+    }
+}
+
+/// Writer of little-endian bit-packed data: bits are packed from the least
+/// significant bit of the first byte. Closed with a padding marker, it makes
+/// a backward bitstream whose bits written last are the first ones read by a
+/// `Bitstream`. Without the marker, it makes a forward stream such as an FSE
+/// table description.
+#[derive(Default)]
+pub struct BitWriter {
+    bytes: Vec<u8>,
+    /// Bits not written to `bytes` yet, in the lowest `bits` bits.
+    container: u64,
+    bits: u8,
+}
+
+impl BitWriter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Appends the lowest `n` bits of `value`.
+    pub fn add_bits(&mut self, value: u64, n: u8) {
+        debug_assert!(n <= MAX_READ_BITS, "too many bits to write: {}", n);
+        self.container |= (value & Bitstream::mask(n)) << self.bits;
+        self.bits += n;
+
+        let len = (self.bits / 8) as usize;
+        self.bytes
+            .extend_from_slice(&self.container.to_le_bytes()[..len]);
+        self.container >>= 8 * len;
+        self.bits -= 8 * len as u8;
+    }
+
+    /// Ends a forward stream, padding its last byte with zeroes.
+    pub fn into_bytes(mut self) -> Vec<u8> {
+        if self.bits > 0 {
+            self.bytes.push(self.container as u8);
         }
+        self.bytes
+    }
 
-        let mask = (1 << n) - 1;
-        (byte >> (8 - start - n)) & mask
+    /// Ends the stream with the padding marker read first by `Bitstream`.
+    pub fn close(mut self) -> Vec<u8> {
+        self.add_bits(1, 1);
+        self.into_bytes()
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::test_rng::TestRng;
 
     #[test]
-    fn get_bits_range() {
-        let byte = 0b01101001;
-        let actual = Bitstream::get_bits_range(byte, 2, 5);
-        let expected = 0b10100;
-        println!("{:b} {:b}", actual, expected);
-        assert_eq!(actual, expected);
+    fn get_bits() {
+        let bytes = 0x1234567801_u64.to_be_bytes();
+        let mut bs = Bitstream::new(&bytes).unwrap();
+        assert_eq!(bs.get_bits(8), 0x78);
+        assert_eq!(bs.get_bits(4), 0x5);
+        assert_eq!(bs.get_bits(8), 0x63);
     }
 
     #[test]
-    fn get_bits() {
-        let bytes = 0x1234567801_u64.to_le_bytes();
-        println!("{:02x?}", bytes);
-        let mut bs = Bitstream::new(bytes.to_vec());
-        let actual = bs.get_bits(8);
-        let expected = 0x78;
-        println!("{:08b} {:08b}", actual, expected);
-        assert_eq!(actual, expected);
+    fn get_bits_more_than_8() {
+        let bytes = 0x1234567801_u64.to_be_bytes();
+        let mut bs = Bitstream::new(&bytes).unwrap();
+        assert_eq!(bs.get_bits(16), 0x7856);
+        assert_eq!(bs.get_bits(4), 0x3);
+        assert_eq!(bs.get_bits(12), 0x412);
+    }
 
-        let actual = bs.get_bits(4);
-        let expected = 0x5;
-        println!("{:04b} {:04b}", actual, expected);
-        assert_eq!(actual, expected);
+    #[test]
+    fn thousand_a() {
+        let bytes = [0xe3, 0x2b, 0x80, 0x05];
+        let mut bs = Bitstream::new(&bytes).unwrap();
+        assert_eq!(bs.get_bits(6), 24);
+        assert_eq!(bs.get_bits(6), 0);
+        assert_eq!(bs.get_bits(5), 21);
+        assert_eq!(bs.bits_remaining(), 9);
+    }
 
-        let actual = bs.get_bits(8);
-        let expected = 0x63;
-        println!("{:08b} {:08b}", actual, expected);
-        assert_eq!(actual, expected);
+    #[test]
+    fn missing_padding_marker() {
+        assert!(Bitstream::new(&[]).is_err());
+        assert!(Bitstream::new(&[0x12, 0x00]).is_err());
     }
 
     #[test]
-    fn get_bits_more_than_8() {
-        let bytes = 0x1234567801_u64.to_le_bytes();
-        println!("{:02x?}", bytes);
-        let mut bs = Bitstream::new(bytes.to_vec());
-        let actual = bs.get_bits(16);
-        let expected = 0x7856;
-        println!("{:08b} {:08b}", actual, expected);
-        assert_eq!(actual, expected);
+    fn overconsumption() {
+        let mut bs = Bitstream::new(&[0xff, 0x03]).unwrap();
+        assert_eq!(bs.get_bits(1), 1);
+        assert_eq!(bs.get_bits(4), 0b1111);
+        assert_eq!(bs.bits_remaining(), 4);
+        assert_eq!(bs.get_bits(8), 0b1111_0000);
+        assert_eq!(bs.bits_remaining(), -4);
+        assert!(bs.check_fully_consumed().is_err());
+        assert_eq!(bs.get_bits(3), 0);
+        assert_eq!(bs.bits_remaining(), -7);
+    }
 
-        let actual = bs.get_bits(4);
-        let expected = 0x3;
-        println!("{:04b} {:04b}", actual, expected);
-        assert_eq!(actual, expected);
+    /// Reads a long stream with widths crossing every container boundary and
+    /// compares with reading bit by bit.
+    #[test]
+    fn long_stream() {
+        let mut bytes = TestRng::new().bytes(100);
+        bytes[99] |= 1;
+        let total_bits = bytes.len() * 8 - 1 - bytes[99].leading_zeros() as usize;
+        let bit = |position: usize| -> u64 {
+            let index = total_bits - 1 - position;
+            (bytes[index / 8] >> (index % 8)) as u64 & 1
+        };
 
-        let actual = bs.get_bits(12);
-        let expected = 0x412;
-        println!("{:08b} {:08b}", actual, expected);
-        assert_eq!(actual, expected);
+        let mut bs = Bitstream::new(&bytes).unwrap();
+        let mut position = 0;
+        for n in (0..=MAX_READ_BITS).cycle() {
+            if position + n as usize > total_bits {
+                break;
+            }
+            let expected = (position..position + n as usize).fold(0, |v, p| (v << 1) | bit(p));
+            assert_eq!(bs.peek_bits(n), expected);
+            assert_eq!(bs.get_bits(n), expected);
+            position += n as usize;
+            assert_eq!(bs.bits_remaining(), (total_bits - position) as isize);
+        }
+
+        let n = (total_bits - position) as u8;
+        bs.consume_bits(n);
+        bs.check_fully_consumed().unwrap();
     }
 
     #[test]
-    fn thousand_a() {
-        let bytes = vec![0x05, 0x80, 0x2b, 0xe3];
-        println!("{:02x?}", bytes);
-        let mut bs = Bitstream::new(bytes);
-        let actual = bs.get_bits(6);
-        let expected = 24;
-        println!("{:06b} {:06b}", actual, expected);
-        assert_eq!(actual, expected);
-
-        let actual = bs.get_bits(6);
-        let expected = 0;
-        println!("{:06b} {:06b}", actual, expected);
-        assert_eq!(actual, expected);
-
-        let actual = bs.get_bits(5);
-        let expected = 21;
-        println!("{:05b} {:05b}", actual, expected);
-        assert_eq!(actual, expected);
+    fn bit_writer() {
+        let mut writer = BitWriter::new();
+        let values = (0..=MAX_READ_BITS)
+            .map(|n| {
+                (
+                    n,
+                    0x0123_4567_89ab_cdef_u64.rotate_left(n as u32) & Bitstream::mask(n),
+                )
+            })
+            .collect::<Vec<_>>();
+        for (n, value) in &values {
+            writer.add_bits(*value, *n);
+        }
+        let bytes = writer.close();
+
+        let mut bs = Bitstream::new(&bytes).unwrap();
+        for (n, value) in values.iter().rev() {
+            assert_eq!(bs.get_bits(*n), *value);
+        }
+        bs.check_fully_consumed().unwrap();
+    }
+
+    #[test]
+    fn bit_writer_padding_marker() {
+        assert_eq!(BitWriter::new().close(), [0x01]);
+
+        let mut writer = BitWriter::new();
+        writer.add_bits(0b101, 3);
+        assert_eq!(writer.close(), [0b1101]);
+
+        // A whole byte is added for the marker of byte-aligned streams.
+        let mut writer = BitWriter::new();
+        writer.add_bits(0xab, 8);
+        writer.add_bits(0, 0);
+        let bytes = writer.close();
+        assert_eq!(bytes, [0xab, 0x01]);
+        let mut bs = Bitstream::new(&bytes).unwrap();
+        assert_eq!(bs.get_bits(8), 0xab);
+        bs.check_fully_consumed().unwrap();
+    }
+
+    #[test]
+    fn bit_writer_forward() {
+        let mut writer = BitWriter::new();
+        writer.add_bits(0x3, 2);
+        writer.add_bits(0xffff_0012, 12);
+        writer.add_bits(0x1_ffff, 17);
+        assert_eq!(writer.into_bytes(), [0x4b, 0xc0, 0xff, 0x7f]);
+        assert_eq!(BitWriter::new().into_bytes(), []);
+    }
+
+    /// Writes many values of pseudo-random widths, so that every alignment
+    /// of the container is crossed, and reads them back backward.
+    #[test]
+    fn bit_writer_round_trip() {
+        let mut rng = TestRng::new();
+        let values = (0..5000)
+            .map(|_| {
+                let state = rng.next_u64();
+                let n = (state >> 58) as u8 % (MAX_READ_BITS + 1);
+                (n, state & Bitstream::mask(n))
+            })
+            .collect::<Vec<_>>();
+
+        let mut writer = BitWriter::new();
+        for (n, value) in &values {
+            writer.add_bits(*value, *n);
+        }
+        let bytes = writer.close();
+        let total_bits = values.iter().map(|(n, _)| *n as usize).sum::<usize>();
+        assert_eq!(bytes.len(), total_bits / 8 + 1);
+
+        let mut bs = Bitstream::new(&bytes).unwrap();
+        for (n, value) in values.iter().rev() {
+            assert_eq!(bs.peek_bits(*n), *value);
+            assert_eq!(bs.get_bits(*n), *value);
+        }
+        bs.check_fully_consumed().unwrap();
     }
 }
@@ -1,10 +1,14 @@
 use log::debug;
 
 use crate::compressed_block::CompressedBlock;
-use crate::Res;
+use crate::frame::DecodingContext;
+use crate::{read_array, Error, Res};
 
-#[derive(Debug, PartialEq, Eq)]
-enum BlockType {
+/// Maximum size of the content of a block, before and after decompression.
+pub const MAX_BLOCK_SIZE: usize = 128 * 1024;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BlockType {
     Raw,
     Rle,
     Compressed,
@@ -15,7 +19,7 @@ enum BlockType {
 pub struct BlockHeader([u8; 3]);
 
 impl BlockHeader {
-    fn from_bytes(bytes: [u8; 3]) -> Self {
+    pub fn from_bytes(bytes: [u8; 3]) -> Self {
         let s = Self(bytes);
 
         debug!("block_header {:02x?}", &bytes[..3]);
@@ -28,6 +32,20 @@ impl BlockHeader {
         s
     }
 
+    /// Header written by an encoder. `block_size` is the size of the content
+    /// following the header, except for RLE blocks where it is the number of
+    /// times their byte is repeated.
+    pub fn new(is_last_block: bool, block_type: BlockType, block_size: usize) -> Self {
+        debug_assert!(block_size <= MAX_BLOCK_SIZE);
+        let value = (block_size as u32) << 3 | (block_type as u32) << 1 | is_last_block as u32;
+        let [a, b, c, _] = value.to_le_bytes();
+        Self([a, b, c])
+    }
+
+    pub fn to_bytes(&self) -> [u8; 3] {
+        self.0
+    }
+
     pub fn is_last_block(&self) -> bool {
         let flag = self.0[0] & 1;
         flag != 0
@@ -40,14 +58,27 @@ impl BlockHeader {
             1 => BlockType::Rle,
             2 => BlockType::Compressed,
             3 => BlockType::Reserved,
-            _ => panic!("Impossible block type"),
+            _ => unreachable!(),
         }
     }
 
     fn block_size(&self) -> u32 {
         let [a, b, c] = self.0;
-        let a = a >> 3;
-        (c as u32) << 16 | (b as u32) << 8 | (a as u32)
+        ((c as u32) << 16 | (b as u32) << 8 | (a as u32)) >> 3
+    }
+
+    /// Number of bytes following the header, a single byte for RLE blocks.
+    pub fn content_len(&self) -> Res<usize> {
+        let block_size = self.block_size() as usize;
+        if block_size > MAX_BLOCK_SIZE {
+            return Err(Error::Corrupted("block size is bigger than 128 KiB"));
+        }
+
+        if self.block_type() == BlockType::Rle {
+            Ok(1)
+        } else {
+            Ok(block_size)
+        }
     }
 }
 
@@ -59,22 +90,11 @@ pub struct Block {
 
 impl Block {
     pub fn from_bytes(bytes: &[u8]) -> Res<Self> {
-        let block_header = BlockHeader::from_bytes(bytes[..3].try_into()?);
-
-        if block_header.block_type() == BlockType::Rle {
-            let block_content = vec![bytes[3]];
-            return Ok(Self {
-                block_header,
-                block_content,
-            });
-        }
-
+        let block_header = BlockHeader::from_bytes(read_array(bytes, "block header")?);
         let block_content = bytes
-            .iter()
-            .skip(3)
-            .take(block_header.block_size() as usize)
-            .copied()
-            .collect();
+            .get(3..3 + block_header.content_len()?)
+            .ok_or(Error::Truncated("block"))?
+            .to_vec();
 
         let s = Self {
             block_header,
@@ -89,16 +109,17 @@ impl Block {
         3 + self.block_content.len()
     }
 
-    pub fn decode(self) -> Vec<u8> {
-        match self.block_header.block_type() {
+    pub fn decode(self, context: &mut DecodingContext) -> Res<Vec<u8>> {
+        let output = match self.block_header.block_type() {
             BlockType::Raw => self.block_content,
             BlockType::Rle => vec![self.block_content[0]; self.block_header.block_size() as usize],
-            BlockType::Reserved => panic!("Impossible reserved block type"),
+            BlockType::Reserved => return Err(Error::ReservedBitSet("block header")),
             BlockType::Compressed => {
-                let mut compressed_block =
-                    CompressedBlock::from_bytes(&self.block_content).unwrap();
-                compressed_block.sequence_execution()
+                let compressed_block = CompressedBlock::from_bytes(&self.block_content, context)?;
+                compressed_block.sequence_execution(context)?
             }
-        }
+        };
+        context.extend_history(&output);
+        Ok(output)
     }
 }
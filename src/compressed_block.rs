@@ -1,11 +1,21 @@
 use log::debug;
 
 use crate::{
-    bitstream::Bitstream,
-    fse::{FseDecoder, FseDecodingTable},
-    Res,
+    bitstream::{BitWriter, Bitstream},
+    block::MAX_BLOCK_SIZE,
+    frame::DecodingContext,
+    fse::{FseDecoder, FseDecodingTable, FseEncoder, FseEncodingTable, FseTableDescription},
+    huffman::{HuffmanDecodingTable, HuffmanEncodingTable, HuffmanTreeDescription},
+    Error, Res,
 };
 
+pub const LITERALS_LENGTH_MAX_SYMBOL: u8 = 35;
+pub const LITERALS_LENGTH_MAX_ACCURACY_LOG: u8 = 9;
+pub const MATCH_LENGTH_MAX_SYMBOL: u8 = 52;
+pub const MATCH_LENGTH_MAX_ACCURACY_LOG: u8 = 9;
+pub const OFFSET_CODE_MAX_SYMBOL: u8 = 31;
+pub const OFFSET_CODE_MAX_ACCURACY_LOG: u8 = 8;
+
 #[derive(Debug)]
 pub struct CompressedBlock {
     literals_section: LiteralsSection,
@@ -13,12 +23,12 @@ pub struct CompressedBlock {
 }
 
 impl CompressedBlock {
-    pub fn from_bytes(mut bytes: &[u8]) -> Res<Self> {
-        let literals_section = LiteralsSection::from_bytes(bytes)?;
+    pub fn from_bytes(mut bytes: &[u8], context: &mut DecodingContext) -> Res<Self> {
+        let literals_section = LiteralsSection::from_bytes(bytes, context)?;
         debug!("LiteralsSection {:02x?}", &bytes[..literals_section.len()]);
         bytes = &bytes[literals_section.len()..];
 
-        let sequences_section = SequencesSection::from_bytes(bytes)?;
+        let sequences_section = SequencesSection::from_bytes(bytes, context)?;
         debug!("Sequences {:?}", sequences_section.sequences);
 
         Ok(Self {
@@ -27,51 +37,94 @@ impl CompressedBlock {
         })
     }
 
-    pub fn sequence_execution(&mut self) -> Vec<u8> {
-        let mut repeated_offsets = [1, 4, 8];
-        let mut output = vec![];
+    /// Writes the content of a compressed block made of the literals of its
+    /// sequences, followed by the sequences.
+    pub fn encode(literals: &[u8], sequences: &[Sequence]) -> Vec<u8> {
+        let mut bytes = LiteralsSection::encode(literals);
+        bytes.extend(SequencesSection::encode(sequences));
+        bytes
+    }
 
-        for sequence in &self.sequences_section.sequences {
-            match &mut self.literals_section.streams {
-                Streams::One(items) => {
-                    let ll = sequence.ll as usize;
-                    output.extend_from_slice(&items[..ll]);
-                    *items = Vec::from(&items[ll..]);
-                }
-                Streams::Four(_) => todo!(),
-            };
+    /// Executes the sequences of the block, matches can reach back into the
+    /// bytes decoded by the previous blocks kept in `context`.
+    pub fn sequence_execution(&self, context: &mut DecodingContext) -> Res<Vec<u8>> {
+        let mut output = vec![];
 
-            let offset_value = match sequence.of {
-                0 => unreachable!(),
-                1 => repeated_offsets[0],
-                2 => {
-                    (repeated_offsets[0], repeated_offsets[1]) =
-                        (repeated_offsets[1], repeated_offsets[0]);
-                    repeated_offsets[0]
-                }
-                3 => {
-                    let n = repeated_offsets[2];
-                    repeated_offsets[2] = repeated_offsets[1];
-                    repeated_offsets[1] = repeated_offsets[0];
-                    repeated_offsets[0] = n;
-                    n
-                }
-                n => {
-                    repeated_offsets[2] = repeated_offsets[1];
-                    repeated_offsets[1] = repeated_offsets[0];
-                    repeated_offsets[0] = n - 3;
-                    n - 3
-                }
-            };
+        let literals = match &self.literals_section.streams {
+            Streams::One(items) => items,
+            Streams::Four(streams) => &streams.concat(),
+        };
+        let mut literals_index = 0;
 
-            let index = output.len() - 1 - offset_value as usize;
+        for sequence in &self.sequences_section.sequences {
+            let ll = sequence.ll as usize;
+            let sequence_literals = literals.get(literals_index..literals_index + ll).ok_or(
+                Error::CorruptBitstream("literals length exceeds the literals"),
+            )?;
+            output.extend_from_slice(sequence_literals);
+            literals_index += ll;
+
+            let offset_value =
+                resolve_offset(&mut context.repeated_offsets, sequence.of, sequence.ll)?;
+
+            let offset = offset_value as usize;
+            if output.len() + sequence.ml as usize > MAX_BLOCK_SIZE {
+                return Err(Error::Corrupted("decoded block is bigger than 128 KiB"));
+            }
+            context.check_offset(offset, output.len())?;
+            let history = &context.history;
             for _ in 0..sequence.ml {
-                output.push(output[index]);
+                let byte = if offset <= output.len() {
+                    output[output.len() - offset]
+                } else {
+                    history[history.len() + output.len() - offset]
+                };
+                output.push(byte);
             }
         }
 
-        output
+        output.extend_from_slice(&literals[literals_index..]);
+
+        Ok(output)
+    }
+}
+
+/// Converts the offset value of a sequence into an actual offset, updating
+/// the repeated offsets history. When the literals length is 0, the repeat
+/// indices are shifted by one and offset value 3 means the first repeated
+/// offset minus one.
+fn resolve_offset(
+    repeated_offsets: &mut [u32; 3],
+    offset_value: u32,
+    literals_length: u32,
+) -> Res<u32> {
+    if offset_value > 3 {
+        let offset = offset_value - 3;
+        *repeated_offsets = [offset, repeated_offsets[0], repeated_offsets[1]];
+        return Ok(offset);
     }
+
+    let repeat_index = if literals_length == 0 {
+        offset_value
+    } else {
+        offset_value - 1
+    };
+
+    let [first, second, third] = *repeated_offsets;
+    *repeated_offsets = match repeat_index {
+        0 => return Ok(first),
+        1 => [second, first, third],
+        2 => [third, first, second],
+        3 => {
+            if first == 1 {
+                return Err(Error::CorruptBitstream("repeated offset 1 minus 1 is 0"));
+            }
+            [first - 1, first, second]
+        }
+        _ => unreachable!(),
+    };
+
+    Ok(repeated_offsets[0])
 }
 
 #[derive(Debug)]
@@ -80,64 +133,221 @@ enum Streams {
     Four([Vec<u8>; 4]),
 }
 
-impl Streams {
-    fn len(&self) -> usize {
-        match self {
-            Streams::One(s) => s.len(),
-            Streams::Four(streams) => streams.iter().map(|s| s.len()).sum(),
-        }
-    }
-}
-
 #[derive(Debug)]
 struct LiteralsSection {
     literals_section_header: LiteralsSectionHeader,
-    huffman_tree_description: Option<()>,
-    jump_table: Option<[u16; 3]>,
+    literals_block_type: LiteralsBlockType,
     streams: Streams,
 }
 
 impl LiteralsSection {
-    pub fn from_bytes(mut bytes: &[u8]) -> Res<Self> {
-        let literals_block_type = LiteralsSectionHeader::literals_block_type(bytes[0]);
+    pub fn from_bytes(mut bytes: &[u8], context: &mut DecodingContext) -> Res<Self> {
         let lsh = LiteralsSectionHeader::from_bytes(bytes)?;
+        let literals_block_type = LiteralsSectionHeader::literals_block_type(bytes[0]);
+        let is_one_stream = LiteralsSectionHeader::is_one_stream(bytes[0]);
         bytes = &bytes[lsh.header_len..];
+        let regenerated_size = lsh.regenerated_size as usize;
+        if regenerated_size > MAX_BLOCK_SIZE {
+            return Err(Error::Corrupted("literals are bigger than 128 KiB"));
+        }
 
-        match literals_block_type {
+        let streams = match literals_block_type {
             LiteralsBlockType::Raw => {
-                let stream = bytes[..lsh.regenerated_size as usize].into();
-                Ok(Self {
-                    literals_section_header: lsh,
-                    huffman_tree_description: None,
-                    jump_table: None,
-                    streams: Streams::One(stream),
-                })
+                let literals = bytes
+                    .get(..regenerated_size)
+                    .ok_or(Error::Truncated("literals section"))?;
+                Streams::One(literals.to_vec())
+            }
+            LiteralsBlockType::Rle => {
+                let byte = *bytes.first().ok_or(Error::Truncated("literals section"))?;
+                Streams::One(vec![byte; regenerated_size])
             }
-            LiteralsBlockType::Rle => Ok(Self {
-                literals_section_header: lsh,
-                huffman_tree_description: None,
-                jump_table: None,
-                streams: Streams::One(bytes[..1].into()),
-            }),
             LiteralsBlockType::Compressed => {
-                todo!();
+                let compressed_size = lsh.compressed_size.unwrap_or_default() as usize;
+                bytes = bytes
+                    .get(..compressed_size)
+                    .ok_or(Error::Truncated("literals section"))?;
+
+                let description = HuffmanTreeDescription::from_bytes(bytes)?;
+                let table = HuffmanDecodingTable::from_tree_description(&description)?;
+                bytes = &bytes[description.len()..];
+
+                let streams = Self::decode_streams(&table, bytes, regenerated_size, is_one_stream)?;
+                context.huffman_table = Some(table);
+                streams
             }
             LiteralsBlockType::Treeless => {
-                todo!();
+                let compressed_size = lsh.compressed_size.unwrap_or_default() as usize;
+                bytes = bytes
+                    .get(..compressed_size)
+                    .ok_or(Error::Truncated("literals section"))?;
+
+                let Some(table) = &context.huffman_table else {
+                    return Err(Error::Corrupted(
+                        "treeless literals without a previous Huffman table",
+                    ));
+                };
+                Self::decode_streams(table, bytes, regenerated_size, is_one_stream)?
             }
+        };
+
+        Ok(Self {
+            literals_section_header: lsh,
+            literals_block_type,
+            streams,
+        })
+    }
+
+    /// Writes the literals Huffman-compressed when it makes them smaller, as
+    /// a single byte when they repeat it, or raw.
+    fn encode(literals: &[u8]) -> Vec<u8> {
+        let size = literals.len() as u32;
+        if let Some(first) = literals.first()
+            && literals.iter().all(|literal| literal == first)
+        {
+            let mut bytes =
+                LiteralsSectionHeader::encode_uncompressed(LiteralsBlockType::Rle, size);
+            bytes.push(*first);
+            return bytes;
+        }
+
+        let mut bytes = LiteralsSectionHeader::encode_uncompressed(LiteralsBlockType::Raw, size);
+        bytes.extend_from_slice(literals);
+        if literals.is_empty() {
+            return bytes;
+        }
+        match Self::encode_compressed(literals) {
+            Some(compressed) if compressed.len() < bytes.len() => compressed,
+            _ => bytes,
         }
     }
 
-    fn len(&self) -> usize {
-        if self.huffman_tree_description.is_some() {
-            todo!();
+    /// Writes at least 2 different literals Huffman-compressed, in a single
+    /// stream when there are few of them. None if the Huffman tree cannot be
+    /// described.
+    fn encode_compressed(literals: &[u8]) -> Option<Vec<u8>> {
+        let mut counts = [0; 256];
+        for literal in literals {
+            counts[*literal as usize] += 1;
+        }
+        let table = HuffmanEncodingTable::from_counts(&counts);
+        let mut content = table.description()?;
+
+        let is_one_stream = literals.len() < 256;
+        if is_one_stream {
+            content.extend(table.encode_stream(literals));
+        } else {
+            content.extend(Self::encode_four_streams(&table, literals));
         }
-        let jump_table_size = if self.jump_table.is_some() { 6 } else { 0 };
-        self.literals_section_header.header_len + jump_table_size + self.streams.len()
+
+        let mut bytes = LiteralsSectionHeader::encode_compressed(
+            literals.len() as u32,
+            content.len() as u32,
+            is_one_stream,
+        );
+        bytes.extend(content);
+        Some(bytes)
+    }
+
+    /// Splits the literals in four streams preceded by the jump table, the
+    /// mirror of `decode_four_streams`.
+    fn encode_four_streams(table: &HuffmanEncodingTable, literals: &[u8]) -> Vec<u8> {
+        let segment_size = literals.len().div_ceil(4);
+        let streams = literals
+            .chunks(segment_size)
+            .map(|segment| table.encode_stream(segment))
+            .collect::<Vec<_>>();
+        debug_assert_eq!(streams.len(), 4);
+
+        let mut bytes = vec![];
+        for stream in &streams[..3] {
+            bytes.extend((stream.len() as u16).to_le_bytes());
+        }
+        bytes.extend(streams.concat());
+        bytes
+    }
+
+    fn decode_streams(
+        table: &HuffmanDecodingTable,
+        bytes: &[u8],
+        regenerated_size: usize,
+        is_one_stream: bool,
+    ) -> Res<Streams> {
+        let streams = if is_one_stream {
+            Streams::One(table.decode_stream(bytes, regenerated_size)?)
+        } else {
+            Streams::Four(Self::decode_four_streams(table, bytes, regenerated_size)?)
+        };
+        Ok(streams)
+    }
+
+    fn decode_four_streams(
+        table: &HuffmanDecodingTable,
+        bytes: &[u8],
+        regenerated_size: usize,
+    ) -> Res<[Vec<u8>; 4]> {
+        if bytes.len() < 6 {
+            return Err(Error::Truncated("jump table"));
+        }
+        let jump_table = [
+            u16::from_le_bytes([bytes[0], bytes[1]]) as usize,
+            u16::from_le_bytes([bytes[2], bytes[3]]) as usize,
+            u16::from_le_bytes([bytes[4], bytes[5]]) as usize,
+        ];
+        debug!("jump_table {:?}", jump_table);
+        let bytes = &bytes[6..];
+
+        let first_streams_size = jump_table.iter().sum::<usize>();
+        if first_streams_size >= bytes.len() {
+            return Err(Error::Corrupted(
+                "jump table stream sizes exceed the literals section size",
+            ));
+        }
+        let stream_sizes = [
+            jump_table[0],
+            jump_table[1],
+            jump_table[2],
+            bytes.len() - first_streams_size,
+        ];
+
+        let segment_size = regenerated_size.div_ceil(4);
+        if segment_size * 3 > regenerated_size {
+            return Err(Error::Corrupted(
+                "regenerated size is too small for four streams",
+            ));
+        }
+        let regenerated_sizes = [
+            segment_size,
+            segment_size,
+            segment_size,
+            regenerated_size - segment_size * 3,
+        ];
+
+        let mut start = 0;
+        let mut streams: [Vec<u8>; 4] = Default::default();
+        for (i, stream) in streams.iter_mut().enumerate() {
+            let end = start + stream_sizes[i];
+            *stream = table.decode_stream(&bytes[start..end], regenerated_sizes[i])?;
+            start = end;
+        }
+
+        Ok(streams)
+    }
+
+    fn len(&self) -> usize {
+        let lsh = &self.literals_section_header;
+        let content_len = match self.literals_block_type {
+            LiteralsBlockType::Raw => lsh.regenerated_size,
+            LiteralsBlockType::Rle => 1,
+            LiteralsBlockType::Compressed | LiteralsBlockType::Treeless => {
+                lsh.compressed_size.unwrap_or_default()
+            }
+        };
+        lsh.header_len + content_len as usize
     }
 }
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 enum LiteralsBlockType {
     Raw,
     Rle,
@@ -162,7 +372,19 @@ impl LiteralsSectionHeader {
     }
 
     pub fn from_bytes(bytes: &[u8]) -> Res<Self> {
-        let s = Self::_from_bytes(bytes);
+        if bytes.is_empty() {
+            return Err(Error::Truncated("literals section header"));
+        }
+        // The header is at most 5 bytes long, its size is only known after
+        // reading the first byte.
+        let mut header = [0; 5];
+        let available = bytes.len().min(header.len());
+        header[..available].copy_from_slice(&bytes[..available]);
+
+        let s = Self::_from_bytes(&header);
+        if s.header_len > bytes.len() {
+            return Err(Error::Truncated("literals section header"));
+        }
 
         debug!("literal_section_header {:02x?}", &bytes[..s.header_len]);
         debug!(
@@ -175,13 +397,57 @@ impl LiteralsSectionHeader {
         Ok(s)
     }
 
+    /// Header of raw or RLE literals, with the shortest size format fitting
+    /// `regenerated_size`.
+    fn encode_uncompressed(
+        literals_block_type: LiteralsBlockType,
+        regenerated_size: u32,
+    ) -> Vec<u8> {
+        let block_type = literals_block_type as u32;
+        if regenerated_size < 32 {
+            vec![(regenerated_size << 3 | block_type) as u8]
+        } else if regenerated_size < 4096 {
+            let header = regenerated_size << 4 | 0b01 << 2 | block_type;
+            header.to_le_bytes()[..2].to_vec()
+        } else {
+            let header = regenerated_size << 4 | 0b11 << 2 | block_type;
+            header.to_le_bytes()[..3].to_vec()
+        }
+    }
+
+    /// Header of Huffman-compressed literals, with the shortest size format
+    /// fitting both sizes. A single stream is only possible with 10-bit
+    /// sizes.
+    fn encode_compressed(
+        regenerated_size: u32,
+        compressed_size: u32,
+        is_one_stream: bool,
+    ) -> Vec<u8> {
+        let block_type = LiteralsBlockType::Compressed as u64;
+        let sizes = regenerated_size.max(compressed_size);
+        debug_assert!(!is_one_stream || sizes < 1 << 10);
+
+        let (size_format, size_bits, len) = if sizes < 1 << 10 {
+            (if is_one_stream { 0b00 } else { 0b01 }, 10, 3)
+        } else if sizes < 1 << 14 {
+            (0b10, 14, 4)
+        } else {
+            (0b11, 18, 5)
+        };
+        let header = block_type
+            | size_format << 2
+            | (regenerated_size as u64) << 4
+            | (compressed_size as u64) << (4 + size_bits);
+        header.to_le_bytes()[..len].to_vec()
+    }
+
     fn literals_block_type(byte: u8) -> LiteralsBlockType {
         match byte & 0b11 {
             0 => LiteralsBlockType::Raw,
             1 => LiteralsBlockType::Rle,
             2 => LiteralsBlockType::Compressed,
             3 => LiteralsBlockType::Treeless,
-            _ => panic!("impossible literals_block_type"),
+            _ => unreachable!(),
         }
     }
 
@@ -211,7 +477,7 @@ impl LiteralsSectionHeader {
                     None,
                     3,
                 ),
-                _ => panic!("impossible size_format"),
+                _ => unreachable!(),
             },
             LiteralsBlockType::Compressed | LiteralsBlockType::Treeless => match size_format {
                 0b00 | 0b01 => Self::new(
@@ -229,7 +495,7 @@ impl LiteralsSectionHeader {
                     Some((bytes[2] >> 6) + (bytes[3] << 2) + (bytes[4] << 10)),
                     5,
                 ),
-                _ => panic!("impossible size_format"),
+                _ => unreachable!(),
             },
         }
     }
@@ -237,12 +503,11 @@ impl LiteralsSectionHeader {
 
 #[derive(Debug)]
 struct SequencesSection {
-    sequences_section_header: SequencesSectionHeader,
     sequences: Vec<Sequence>,
 }
 
 impl SequencesSection {
-    fn from_bytes(mut bytes: &[u8]) -> Res<Self> {
+    fn from_bytes(mut bytes: &[u8], context: &mut DecodingContext) -> Res<Self> {
         let sequences_section_header = SequencesSectionHeader::from_bytes(bytes)?;
         debug!(
             "sequences_section_header {:02x?}",
@@ -251,45 +516,49 @@ impl SequencesSection {
         debug!("{:?}", sequences_section_header);
         bytes = &bytes[sequences_section_header.len()..];
 
-        if sequences_section_header
-            .symbol_compression_modes
-            .literal_lengths_mode()
-            != CompressionMode::Predefined
-        {
-            todo!();
-        }
-
-        if sequences_section_header
-            .symbol_compression_modes
-            .offsets_mode()
-            != CompressionMode::Predefined
-        {
-            todo!();
-        }
-
-        if sequences_section_header
-            .symbol_compression_modes
-            .match_lengths_mode()
-            != CompressionMode::Predefined
-        {
-            todo!();
+        if sequences_section_header.number_of_sequences == 0 {
+            return Ok(Self { sequences: vec![] });
         }
 
-        let ll_table = FseDecodingTable::literals_length_default_distribution();
-        let ml_table = FseDecodingTable::match_lengths_default_distribution();
-        let of_table = FseDecodingTable::offset_codes_default_distribution();
-
-        let bytes = bytes.iter().rev().copied().collect::<Vec<_>>();
-        debug!("{:02x?}", bytes);
-        let mut bs = Bitstream::new(bytes);
+        let modes = &sequences_section_header.symbol_compression_modes;
+        let ll_table = Self::decoding_table(
+            &mut bytes,
+            modes.literal_lengths_mode(),
+            FseDecodingTable::literals_length_default_distribution,
+            LITERALS_LENGTH_MAX_SYMBOL,
+            LITERALS_LENGTH_MAX_ACCURACY_LOG,
+            context.literals_length_table.as_ref(),
+        )?;
+        let of_table = Self::decoding_table(
+            &mut bytes,
+            modes.offsets_mode(),
+            FseDecodingTable::offset_codes_default_distribution,
+            OFFSET_CODE_MAX_SYMBOL,
+            OFFSET_CODE_MAX_ACCURACY_LOG,
+            context.offset_table.as_ref(),
+        )?;
+        let ml_table = Self::decoding_table(
+            &mut bytes,
+            modes.match_lengths_mode(),
+            FseDecodingTable::match_lengths_default_distribution,
+            MATCH_LENGTH_MAX_SYMBOL,
+            MATCH_LENGTH_MAX_ACCURACY_LOG,
+            context.match_length_table.as_ref(),
+        )?;
+
+        context.literals_length_table = Some(ll_table.clone());
+        context.offset_table = Some(of_table.clone());
+        context.match_length_table = Some(ml_table.clone());
+
+        let mut bs = Bitstream::new(bytes)?;
 
         let ll_init_state = bs.get_bits(ll_table.accuracy_log());
         let of_init_state = bs.get_bits(of_table.accuracy_log());
         let ml_init_state = bs.get_bits(ml_table.accuracy_log());
 
-        let mut ll_decoder = FseDecoder::new(ll_table, ll_init_state as u8);
-        let mut ml_decoder = FseDecoder::new(ml_table, ml_init_state as u8);
-        let mut of_decoder = FseDecoder::new(of_table, of_init_state as u8);
+        let mut ll_decoder = FseDecoder::new(ll_table, ll_init_state as u16);
+        let mut ml_decoder = FseDecoder::new(ml_table, ml_init_state as u16);
+        let mut of_decoder = FseDecoder::new(of_table, of_init_state as u16);
         debug!(
             "init states: {}, {}, {}",
             ll_init_state, ml_init_state, of_init_state
@@ -304,32 +573,115 @@ impl SequencesSection {
             let of_code = of_decoder.symbol();
             debug!("codes: {}, {}, {}", ll_code, ml_code, of_code);
 
-            let (baseline, num_bits) = Self::literals_length_code(ll_code);
-            let ll = baseline + bs.get_bits(num_bits) as u32;
+            let of = (1 << of_code) + bs.get_bits(of_code) as u32;
 
             let (baseline, num_bits) = Self::match_length_code(ml_code);
             let ml = baseline + bs.get_bits(num_bits) as u32;
 
-            let of = (1 << of_code) + bs.get_bits(of_code) as u32;
+            let (baseline, num_bits) = Self::literals_length_code(ll_code);
+            let ll = baseline + bs.get_bits(num_bits) as u32;
 
             sequences.push(Sequence { ll, ml, of });
 
             if i != sequences_section_header.number_of_sequences - 1 {
-                let ll_state = ll_decoder.baseline() + bs.get_bits(ll_decoder.num_bits()) as u8;
-                ll_decoder.set_state(ll_state);
+                ll_decoder.update_state(&mut bs);
+                ml_decoder.update_state(&mut bs);
+                of_decoder.update_state(&mut bs);
+            }
+        }
 
-                let ml_state = ml_decoder.baseline() + bs.get_bits(ml_decoder.num_bits()) as u8;
-                ml_decoder.set_state(ml_state);
+        bs.check_fully_consumed()?;
 
-                let of_state = of_decoder.baseline() + bs.get_bits(of_decoder.num_bits()) as u8;
-                of_decoder.set_state(of_state);
-            }
+        Ok(Self { sequences })
+    }
+
+    /// Writes the sequences, each type of code with the cheapest of the
+    /// predefined table, an RLE table or a table fitted to the block. The
+    /// bitstream is written backward, starting from the last sequence.
+    fn encode(sequences: &[Sequence]) -> Vec<u8> {
+        let mut bytes = SequencesSectionHeader::encode(sequences.len() as u32);
+        let Some(last) = sequences.last() else {
+            return bytes;
+        };
+
+        let codes = sequences.iter().map(SequenceCodes::new).collect::<Vec<_>>();
+        let ll = SymbolTable::choose(
+            codes.iter().map(|codes| codes.ll_code),
+            FseEncodingTable::literals_length_default_distribution,
+            LITERALS_LENGTH_MAX_ACCURACY_LOG,
+        );
+        let of = SymbolTable::choose(
+            codes.iter().map(|codes| codes.of_code),
+            FseEncodingTable::offset_codes_default_distribution,
+            OFFSET_CODE_MAX_ACCURACY_LOG,
+        );
+        let ml = SymbolTable::choose(
+            codes.iter().map(|codes| codes.ml_code),
+            FseEncodingTable::match_lengths_default_distribution,
+            MATCH_LENGTH_MAX_ACCURACY_LOG,
+        );
+        bytes.push(((ll.mode as u8) << 6) | ((of.mode as u8) << 4) | ((ml.mode as u8) << 2));
+        for table in [&ll, &of, &ml] {
+            bytes.extend_from_slice(&table.description);
         }
 
-        Ok(Self {
-            sequences_section_header,
-            sequences,
-        })
+        let last_codes = &codes[codes.len() - 1];
+        let mut writer = BitWriter::new();
+        let mut ll_encoder = FseEncoder::new(&ll.table, last_codes.ll_code);
+        let mut of_encoder = FseEncoder::new(&of.table, last_codes.of_code);
+        let mut ml_encoder = FseEncoder::new(&ml.table, last_codes.ml_code);
+        last_codes.write_extra_bits(&mut writer, last);
+
+        for (sequence, codes) in sequences.iter().zip(&codes).rev().skip(1) {
+            of_encoder.encode(&mut writer, codes.of_code);
+            ml_encoder.encode(&mut writer, codes.ml_code);
+            ll_encoder.encode(&mut writer, codes.ll_code);
+            codes.write_extra_bits(&mut writer, sequence);
+        }
+
+        ml_encoder.flush(&mut writer);
+        of_encoder.flush(&mut writer);
+        ll_encoder.flush(&mut writer);
+        bytes.extend(writer.close());
+        bytes
+    }
+
+    /// Reads the decoding table of one symbol type according to its
+    /// compression mode, advancing `bytes` past its table description.
+    /// `previous_table` is the table used by the previous compressed block
+    /// of the frame.
+    fn decoding_table(
+        bytes: &mut &[u8],
+        mode: CompressionMode,
+        default_table: fn() -> FseDecodingTable,
+        max_symbol: u8,
+        max_accuracy_log: u8,
+        previous_table: Option<&FseDecodingTable>,
+    ) -> Res<FseDecodingTable> {
+        match mode {
+            CompressionMode::Predefined => Ok(default_table()),
+            CompressionMode::Rle => {
+                let symbol = *bytes.first().ok_or(Error::Truncated("sequences section"))?;
+                *bytes = &bytes[1..];
+
+                if symbol > max_symbol {
+                    return Err(Error::Corrupted("RLE symbol is out of range"));
+                }
+                Ok(FseDecodingTable::rle(symbol))
+            }
+            CompressionMode::FseCompressed => {
+                let description =
+                    FseTableDescription::from_bytes(bytes, max_symbol, max_accuracy_log)?;
+                *bytes = &bytes[description.len()..];
+                FseDecodingTable::from_description(&description)
+            }
+            CompressionMode::Repeat => match previous_table {
+                Some(table) => Ok(table.clone()),
+                None => Err(Error::Corrupted(
+                    "repeat compression mode without a previous table",
+                )),
+            },
+        }
     }
 
     fn literals_length_code(literals_length_code: u8) -> (u32, u8) {
@@ -355,7 +707,7 @@ impl SequencesSection {
             33 => (16384, 14),
             34 => (32768, 15),
             35 => (65536, 16),
-            _ => panic!("impossible literals_length_code"),
+            _ => unreachable!(),
         }
     }
 
@@ -383,24 +735,120 @@ impl SequencesSection {
             50 => (16387, 14),
             51 => (32771, 15),
             52 => (65539, 16),
-            _ => panic!("impossible literals_length_code"),
+            _ => unreachable!(),
+        }
+    }
+}
+
+/// Encoding table of one type of code, with the compression mode and the
+/// table description to write in the sequences section.
+struct SymbolTable {
+    mode: CompressionMode,
+    description: Vec<u8>,
+    table: FseEncodingTable,
+}
+
+impl SymbolTable {
+    /// Picks the table taking the fewest bits to encode `codes`, including
+    /// the size of its description.
+    fn choose(
+        codes: impl Iterator<Item = u8>,
+        default_table: fn() -> FseEncodingTable,
+        max_accuracy_log: u8,
+    ) -> Self {
+        let mut counts = vec![0u32; 256];
+        for code in codes {
+            counts[code as usize] += 1;
+        }
+        let used = counts.iter().filter(|count| **count > 0).count();
+        if used == 1 {
+            let symbol = counts.iter().position(|count| *count > 0).unwrap() as u8;
+            return Self {
+                mode: CompressionMode::Rle,
+                description: vec![symbol],
+                table: FseEncodingTable::rle(symbol),
+            };
+        }
+
+        let fitted = FseEncodingTable::from_counts(&counts, max_accuracy_log);
+        let description = fitted.description();
+        let fitted_bits = (description.len() * 8) as f64 + fitted.estimated_bits(&counts).unwrap();
+
+        let predefined = default_table();
+        match predefined.estimated_bits(&counts) {
+            Some(bits) if bits <= fitted_bits => Self {
+                mode: CompressionMode::Predefined,
+                description: vec![],
+                table: predefined,
+            },
+            _ => Self {
+                mode: CompressionMode::FseCompressed,
+                description,
+                table: fitted,
+            },
+        }
+    }
+}
+
+/// Codes of the values of a sequence, with the number of extra bits
+/// following each code.
+struct SequenceCodes {
+    ll_code: u8,
+    ll_num_bits: u8,
+    ml_code: u8,
+    ml_num_bits: u8,
+    of_code: u8,
+}
+
+impl SequenceCodes {
+    fn new(sequence: &Sequence) -> Self {
+        let ll_code = (0..=LITERALS_LENGTH_MAX_SYMBOL)
+            .rev()
+            .find(|code| SequencesSection::literals_length_code(*code).0 <= sequence.ll)
+            .unwrap();
+        let ml_code = (0..=MATCH_LENGTH_MAX_SYMBOL)
+            .rev()
+            .find(|code| SequencesSection::match_length_code(*code).0 <= sequence.ml)
+            .unwrap();
+
+        Self {
+            ll_code,
+            ll_num_bits: SequencesSection::literals_length_code(ll_code).1,
+            ml_code,
+            ml_num_bits: SequencesSection::match_length_code(ml_code).1,
+            of_code: sequence.of.ilog2() as u8,
         }
     }
+
+    /// Writes the extra bits in the reverse order of their decoding.
+    fn write_extra_bits(&self, writer: &mut BitWriter, sequence: &Sequence) {
+        let ll_baseline = SequencesSection::literals_length_code(self.ll_code).0;
+        let ml_baseline = SequencesSection::match_length_code(self.ml_code).0;
+        writer.add_bits((sequence.ll - ll_baseline) as u64, self.ll_num_bits);
+        writer.add_bits((sequence.ml - ml_baseline) as u64, self.ml_num_bits);
+        writer.add_bits(sequence.of as u64, self.of_code);
+    }
 }
 
 #[derive(Debug)]
 struct SequencesSectionHeader {
-    number_of_sequences: u16,
+    number_of_sequences: u32,
     number_of_sequences_size: usize,
     symbol_compression_modes: SymbolCompressionModes,
 }
 
 impl SequencesSectionHeader {
     fn from_bytes(mut bytes: &[u8]) -> Res<Self> {
+        let first_byte = *bytes
+            .first()
+            .ok_or(Error::Truncated("sequences section header"))?;
+        let number_of_sequences_size = Self::number_of_sequences_size(first_byte);
+        if bytes.len() < number_of_sequences_size + 1 {
+            return Err(Error::Truncated("sequences section header"));
+        }
         let number_of_sequences = Self::number_of_sequences(bytes);
-        let number_of_sequences_size = Self::number_of_sequences_size(bytes[0]);
         bytes = &bytes[number_of_sequences_size..];
-        let symbol_compression_modes = SymbolCompressionModes::new(bytes[0]);
+        let symbol_compression_modes = SymbolCompressionModes::new(bytes[0])?;
         Ok(Self {
             number_of_sequences,
             number_of_sequences_size,
@@ -408,15 +856,28 @@ impl SequencesSectionHeader {
         })
     }
 
-    fn number_of_sequences(bytes: &[u8]) -> u16 {
+    /// Number of sequences, without the symbol compression modes.
+    fn encode(number_of_sequences: u32) -> Vec<u8> {
+        let n = number_of_sequences;
+        if n < 128 {
+            vec![n as u8]
+        } else if n < 0x7F00 {
+            vec![(n >> 8) as u8 + 128, n as u8]
+        } else {
+            let [low, high, ..] = (n - 0x7F00).to_le_bytes();
+            vec![255, low, high]
+        }
+    }
+
+    fn number_of_sequences(bytes: &[u8]) -> u32 {
         if bytes[0] == 0 {
             0
         } else if bytes[0] < 128 {
             bytes[0].into()
         } else if bytes[0] < 255 {
-            ((bytes[0] as u16 - 128) << 8) + bytes[1] as u16
+            ((bytes[0] as u32 - 128) << 8) + bytes[1] as u32
         } else {
-            (bytes[1] as u16) << (8 + bytes[2] as u16)
+            bytes[1] as u32 + ((bytes[2] as u32) << 8) + 0x7F00
         }
     }
 
@@ -437,7 +898,7 @@ impl SequencesSectionHeader {
     }
 }
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 enum CompressionMode {
     Predefined,
     Rle,
@@ -449,16 +910,18 @@ enum CompressionMode {
 struct SymbolCompressionModes(u8);
 
 impl SymbolCompressionModes {
-    fn new(byte: u8) -> Self {
+    fn new(byte: u8) -> Res<Self> {
         let s = Self(byte);
-        assert_eq!(s.reserved(), 0);
+        if s.reserved() != 0 {
+            return Err(Error::ReservedBitSet("symbol compression modes"));
+        }
         debug!(
             "literal lengths {:?}, offsets {:?} match lengths {:?}",
             s.literal_lengths_mode(),
             s.offsets_mode(),
             s.match_lengths_mode()
         );
-        s
+        Ok(s)
     }
 
     fn get_2_bits(&self, n: u8) -> u8 {
@@ -471,7 +934,7 @@ impl SymbolCompressionModes {
             1 => CompressionMode::Rle,
             2 => CompressionMode::FseCompressed,
             3 => CompressionMode::Repeat,
-            _ => panic!("impossible compression mode"),
+            _ => unreachable!(),
         }
     }
 
@@ -492,9 +955,250 @@ impl SymbolCompressionModes {
     }
 }
 
+/// Literals length, match length and offset value of a sequence. Offset
+/// values 1 to 3 are repeat codes, the others are offsets plus 3.
 #[derive(Debug)]
-struct Sequence {
-    ll: u32,
-    ml: u32,
-    of: u32,
+pub struct Sequence {
+    pub ll: u32,
+    pub ml: u32,
+    pub of: u32,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dictionary::Dictionary;
+    use crate::test_rng::TestRng;
+
+    /// Literals section header with size format 0b00, followed by the RFC
+    /// example weights when `block_type` is compressed and by a stream
+    /// encoding the literals 0, 1, 2, 5 and 4.
+    fn literals_section(block_type: u8) -> Vec<u8> {
+        let tree_description = [132, 0x43, 0x20, 0x10];
+        let stream = [0x10, 0x69];
+
+        let regenerated_size = 5u8;
+        let mut compressed_size = stream.len() as u8;
+        if block_type == 2 {
+            compressed_size += tree_description.len() as u8;
+        }
+
+        let mut bytes = vec![
+            block_type | (regenerated_size << 4),
+            compressed_size << 6,
+            compressed_size >> 2,
+        ];
+        if block_type == 2 {
+            bytes.extend_from_slice(&tree_description);
+        }
+        bytes.extend_from_slice(&stream);
+        bytes
+    }
+
+    #[test]
+    fn encode_literals_sections() {
+        let text = b"literals of a text, Huffman-compressed when long enough. ".repeat(200);
+        let random = TestRng::new().bytes(1000);
+
+        for (literals, block_type) in [
+            (&b""[..], LiteralsBlockType::Raw),
+            (b"a", LiteralsBlockType::Rle),
+            (&[b'z'; 5000], LiteralsBlockType::Rle),
+            (b"abc", LiteralsBlockType::Raw),
+            (&text[..200], LiteralsBlockType::Compressed),
+            (&text[..1000], LiteralsBlockType::Compressed),
+            (&text, LiteralsBlockType::Compressed),
+            (&random, LiteralsBlockType::Raw),
+        ] {
+            let bytes = LiteralsSection::encode(literals);
+            let mut context = DecodingContext::new(1 << 10);
+            let section = LiteralsSection::from_bytes(&bytes, &mut context).unwrap();
+            assert_eq!(section.literals_block_type, block_type);
+            assert_eq!(section.len(), bytes.len());
+            let actual = match section.streams {
+                Streams::One(literals) => literals,
+                Streams::Four(streams) => streams.concat(),
+            };
+            assert_eq!(actual, literals);
+        }
+    }
+
+    #[test]
+    fn treeless_literals_reuse_previous_table() {
+        let mut context = DecodingContext::new(1 << 10);
+
+        let compressed = literals_section(2);
+        let section = LiteralsSection::from_bytes(&compressed, &mut context).unwrap();
+        assert_eq!(section.len(), compressed.len());
+
+        let treeless = literals_section(3);
+        let section = LiteralsSection::from_bytes(&treeless, &mut context).unwrap();
+        assert_eq!(section.len(), treeless.len());
+        let Streams::One(literals) = section.streams else {
+            panic!("expected a single stream");
+        };
+        assert_eq!(literals, [0, 1, 2, 5, 4]);
+    }
+
+    /// Sequences section holding one sequence with literals length 2, match
+    /// length 4 and offset value 5, using the given compression modes.
+    fn rle_sequences_section(modes: u8) -> Vec<u8> {
+        let mut bytes = vec![1, modes];
+        if modes == 0x54 {
+            // Literals length code 2, offset code 2 and match length code 1.
+            bytes.extend_from_slice(&[2, 2, 1]);
+        }
+        // Padding marker followed by the 2 offset extra bits.
+        bytes.push(0b101);
+        bytes
+    }
+
+    #[test]
+    fn rle_and_repeat_sequence_tables() {
+        let mut context = DecodingContext::new(1 << 10);
+
+        for modes in [0x54, 0xfc] {
+            let bytes = rle_sequences_section(modes);
+            let section = SequencesSection::from_bytes(&bytes, &mut context).unwrap();
+            let [sequence] = section.sequences.as_slice() else {
+                panic!("expected a single sequence");
+            };
+            assert_eq!((sequence.ll, sequence.ml, sequence.of), (2, 4, 5));
+        }
+    }
+
+    #[test]
+    fn repeat_sequence_tables_without_previous_block() {
+        let mut context = DecodingContext::new(1 << 10);
+        let bytes = rle_sequences_section(0xfc);
+        assert!(SequencesSection::from_bytes(&bytes, &mut context).is_err());
+    }
+
+    /// Executes a single sequence with a match length of 3 after the
+    /// history `abcdefghijklmnop` and the repeated offsets 2, 5 and 9.
+    /// Returns the matched bytes and the updated repeated offsets.
+    fn execute_sequence(ll: u32, of: u32) -> Res<(Vec<u8>, [u32; 3])> {
+        let literals = b"XY"[..ll as usize].to_vec();
+        let block = CompressedBlock {
+            literals_section: LiteralsSection {
+                literals_section_header: LiteralsSectionHeader::new(ll, None, 1),
+                literals_block_type: LiteralsBlockType::Raw,
+                streams: Streams::One(literals),
+            },
+            sequences_section: SequencesSection {
+                sequences: vec![Sequence { ll, ml: 3, of }],
+            },
+        };
+
+        let mut context = DecodingContext::new(1 << 10);
+        context.extend_history(b"abcdefghijklmnop");
+        context.repeated_offsets = [2, 5, 9];
+
+        let output = block.sequence_execution(&mut context)?;
+        Ok((output[ll as usize..].to_vec(), context.repeated_offsets))
+    }
+
+    #[test]
+    fn repeated_offsets_with_literals() {
+        assert_eq!(
+            execute_sequence(2, 1).unwrap(),
+            (b"XYX".to_vec(), [2, 5, 9])
+        );
+        assert_eq!(
+            execute_sequence(2, 2).unwrap(),
+            (b"nop".to_vec(), [5, 2, 9])
+        );
+        assert_eq!(
+            execute_sequence(2, 3).unwrap(),
+            (b"jkl".to_vec(), [9, 2, 5])
+        );
+        assert_eq!(
+            execute_sequence(2, 10).unwrap(),
+            (b"lmn".to_vec(), [7, 2, 5])
+        );
+    }
+
+    #[test]
+    fn repeated_offsets_without_literals() {
+        assert_eq!(
+            execute_sequence(0, 1).unwrap(),
+            (b"lmn".to_vec(), [5, 2, 9])
+        );
+        assert_eq!(
+            execute_sequence(0, 2).unwrap(),
+            (b"hij".to_vec(), [9, 2, 5])
+        );
+        assert_eq!(
+            execute_sequence(0, 3).unwrap(),
+            (b"ppp".to_vec(), [1, 2, 5])
+        );
+        assert_eq!(
+            execute_sequence(0, 10).unwrap(),
+            (b"jkl".to_vec(), [7, 2, 5])
+        );
+    }
+
+    /// Block made of the literal `X` followed by a match of length 3 at
+    /// `offset`.
+    fn match_block(offset: u32) -> CompressedBlock {
+        CompressedBlock {
+            literals_section: LiteralsSection {
+                literals_section_header: LiteralsSectionHeader::new(1, None, 1),
+                literals_block_type: LiteralsBlockType::Raw,
+                streams: Streams::One(vec![b'X']),
+            },
+            sequences_section: SequencesSection {
+                sequences: vec![Sequence {
+                    ll: 1,
+                    ml: 3,
+                    of: offset + 3,
+                }],
+            },
+        }
+    }
+
+    #[test]
+    fn match_offsets_stay_within_the_window() {
+        let mut context = DecodingContext::new(16);
+        context.extend_history(&[b'a'; 30]);
+        assert!(match_block(16).sequence_execution(&mut context).is_ok());
+        assert!(match_block(17).sequence_execution(&mut context).is_err());
+    }
+
+    #[test]
+    fn match_offsets_reach_into_the_dictionary() {
+        let mut context = DecodingContext::new(16);
+        context.load_dictionary(&Dictionary::from_raw_content(vec![b'd'; 20]));
+        context.extend_history(&[b'a'; 4]);
+        let output = match_block(24).sequence_execution(&mut context).unwrap();
+        assert_eq!(output, b"Xddd");
+        assert!(match_block(26).sequence_execution(&mut context).is_err());
+    }
+
+    #[test]
+    fn dictionary_out_of_reach_after_the_window() {
+        let mut context = DecodingContext::new(16);
+        context.load_dictionary(&Dictionary::from_raw_content(vec![b'd'; 20]));
+        // 15 bytes and the literal of the block fill the window.
+        context.extend_history(&[b'a'; 15]);
+        let output = match_block(18).sequence_execution(&mut context).unwrap();
+        assert_eq!(output, b"Xdda");
+
+        context.extend_history(&[b'a'; 1]);
+        assert!(match_block(16).sequence_execution(&mut context).is_ok());
+        assert!(match_block(18).sequence_execution(&mut context).is_err());
+    }
+
+    #[test]
+    fn repeated_offset_minus_one_is_zero() {
+        let mut repeated_offsets = [1, 4, 8];
+        assert!(resolve_offset(&mut repeated_offsets, 3, 0).is_err());
+    }
+
+    #[test]
+    fn treeless_literals_without_table() {
+        let mut context = DecodingContext::new(1 << 10);
+        let treeless = literals_section(3);
+        assert!(LiteralsSection::from_bytes(&treeless, &mut context).is_err());
+    }
 }
@@ -0,0 +1,94 @@
+use std::io::{self, Read};
+
+use crate::dictionary::Dictionary;
+use crate::push_decoder::PushDecoder;
+
+/// Largest chunk of input read at once, when skipping skippable frames.
+const MAX_READ_SIZE: usize = 1 << 17;
+
+/// Streaming decoder reading compressed frames from `reader` one block at a
+/// time. Only the window of the current frame is kept in memory, on top of
+/// the last decoded block. No byte is read past the end of the last frame.
+pub struct ZstdDecoder<R: Read> {
+    reader: R,
+    decoder: PushDecoder,
+    input: Vec<u8>,
+    output: Vec<u8>,
+    output_position: usize,
+}
+
+impl<R: Read> ZstdDecoder<R> {
+    pub fn new(reader: R) -> Self {
+        Self {
+            reader,
+            decoder: PushDecoder::new(),
+            input: vec![],
+            output: vec![],
+            output_position: 0,
+        }
+    }
+
+    /// Enables or disables the verification of the content checksum of
+    /// frames, enabled by default.
+    pub fn verify_checksums(mut self, verify_checksums: bool) -> Self {
+        self.decoder = self.decoder.verify_checksums(verify_checksums);
+        self
+    }
+
+    /// Makes a dictionary available to decode the frames referencing its ID.
+    /// Frames without a dictionary ID are decoded with the first dictionary.
+    pub fn with_dictionary(mut self, dictionary: Dictionary) -> Self {
+        self.decoder = self.decoder.with_dictionary(dictionary);
+        self
+    }
+
+    /// References the content frames were compressed against, used instead
+    /// of any dictionary.
+    pub fn with_prefix(mut self, prefix: Vec<u8>) -> Self {
+        self.decoder = self.decoder.with_prefix(prefix);
+        self
+    }
+
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+
+    /// Feeds the decoder with the bytes it needs to make progress. Returns
+    /// false at the end of the input.
+    fn fill_output(&mut self) -> io::Result<bool> {
+        let bytes_needed = self.decoder.bytes_needed().min(MAX_READ_SIZE);
+        self.input.resize(bytes_needed, 0);
+
+        let read = loop {
+            match self.reader.read(&mut self.input) {
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                result => break result?,
+            }
+        };
+        if read == 0 {
+            self.decoder.finish()?;
+            return Ok(false);
+        }
+
+        self.decoder.feed(&self.input[..read])?;
+        self.output = self.decoder.take_output();
+        self.output_position = 0;
+        Ok(true)
+    }
+}
+
+impl<R: Read> Read for ZstdDecoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.output_position == self.output.len() {
+            if buf.is_empty() || !self.fill_output()? {
+                return Ok(0);
+            }
+        }
+
+        let available = &self.output[self.output_position..];
+        let n = available.len().min(buf.len());
+        buf[..n].copy_from_slice(&available[..n]);
+        self.output_position += n;
+        Ok(n)
+    }
+}
@@ -0,0 +1,139 @@
+// https://datatracker.ietf.org/doc/html/rfc8878#name-dictionary-format
+
+use log::debug;
+
+use crate::{
+    compressed_block::{
+        LITERALS_LENGTH_MAX_ACCURACY_LOG, LITERALS_LENGTH_MAX_SYMBOL,
+        MATCH_LENGTH_MAX_ACCURACY_LOG, MATCH_LENGTH_MAX_SYMBOL, OFFSET_CODE_MAX_ACCURACY_LOG,
+        OFFSET_CODE_MAX_SYMBOL,
+    },
+    fse::{FseDecodingTable, FseTableDescription},
+    huffman::{HuffmanDecodingTable, HuffmanTreeDescription},
+    read_array, Error, Res,
+};
+
+pub const DICTIONARY_MAGIC_NUMBER: u32 = 0xEC30A437;
+
+/// Dictionary providing the history used at the start of a frame, and for
+/// dictionaries in the standard format, its entropy tables and repeated
+/// offsets.
+#[derive(Debug)]
+pub struct Dictionary {
+    id: u32,
+    pub(crate) entropy_tables: Option<EntropyTables>,
+    pub(crate) repeated_offsets: [u32; 3],
+    pub(crate) content: Vec<u8>,
+}
+
+#[derive(Debug)]
+pub(crate) struct EntropyTables {
+    pub(crate) huffman_table: HuffmanDecodingTable,
+    pub(crate) offset_table: FseDecodingTable,
+    pub(crate) match_length_table: FseDecodingTable,
+    pub(crate) literals_length_table: FseDecodingTable,
+}
+
+impl Dictionary {
+    pub fn from_bytes(mut bytes: &[u8]) -> Res<Self> {
+        let magic_number = u32::from_le_bytes(read_array(bytes, "dictionary")?);
+        if magic_number != DICTIONARY_MAGIC_NUMBER {
+            return Err(Error::InvalidMagic(magic_number));
+        }
+
+        let id = u32::from_le_bytes(read_array(&bytes[4..], "dictionary")?);
+        debug!("dictionary_id {}", id);
+        bytes = &bytes[8..];
+
+        let description = HuffmanTreeDescription::from_bytes(bytes)?;
+        let huffman_table = HuffmanDecodingTable::from_tree_description(&description)?;
+        bytes = &bytes[description.len()..];
+
+        let offset_table = Self::fse_table(
+            &mut bytes,
+            OFFSET_CODE_MAX_SYMBOL,
+            OFFSET_CODE_MAX_ACCURACY_LOG,
+        )?;
+        let match_length_table = Self::fse_table(
+            &mut bytes,
+            MATCH_LENGTH_MAX_SYMBOL,
+            MATCH_LENGTH_MAX_ACCURACY_LOG,
+        )?;
+        let literals_length_table = Self::fse_table(
+            &mut bytes,
+            LITERALS_LENGTH_MAX_SYMBOL,
+            LITERALS_LENGTH_MAX_ACCURACY_LOG,
+        )?;
+
+        let offsets: [u8; 12] = read_array(bytes, "dictionary")?;
+        let repeated_offsets = [
+            u32::from_le_bytes([offsets[0], offsets[1], offsets[2], offsets[3]]),
+            u32::from_le_bytes([offsets[4], offsets[5], offsets[6], offsets[7]]),
+            u32::from_le_bytes([offsets[8], offsets[9], offsets[10], offsets[11]]),
+        ];
+        let content = bytes[12..].to_vec();
+        debug!("repeated_offsets {:?}", repeated_offsets);
+
+        if repeated_offsets
+            .iter()
+            .any(|offset| *offset == 0 || *offset as usize > content.len())
+        {
+            return Err(Error::Corrupted("invalid dictionary repeated offsets"));
+        }
+
+        Ok(Self {
+            id,
+            entropy_tables: Some(EntropyTables {
+                huffman_table,
+                offset_table,
+                match_length_table,
+                literals_length_table,
+            }),
+            repeated_offsets,
+            content,
+        })
+    }
+
+    /// Dictionary made only of content, without ID nor entropy tables. It
+    /// can also be used as a reference prefix, like when compressing with
+    /// `--patch-from`.
+    pub fn from_raw_content(content: Vec<u8>) -> Self {
+        Self {
+            id: 0,
+            entropy_tables: None,
+            repeated_offsets: [1, 4, 8],
+            content,
+        }
+    }
+
+    fn fse_table(bytes: &mut &[u8], max_symbol: u8, max_accuracy_log: u8) -> Res<FseDecodingTable> {
+        let description = FseTableDescription::from_bytes(bytes, max_symbol, max_accuracy_log)?;
+        *bytes = &bytes[description.len()..];
+        FseDecodingTable::from_description(&description)
+    }
+
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+}
+
+/// Picks the dictionary to decode a frame with. A prefix takes precedence
+/// over dictionaries, and frames without a dictionary ID use the first one.
+pub fn find_dictionary<'a>(
+    dictionaries: &'a [Dictionary],
+    prefix: Option<&'a Dictionary>,
+    dictionary_id: u32,
+) -> Res<Option<&'a Dictionary>> {
+    if prefix.is_some() {
+        return Ok(prefix);
+    }
+    if dictionary_id == 0 {
+        return Ok(dictionaries.first());
+    }
+
+    let dictionary = dictionaries
+        .iter()
+        .find(|dictionary| dictionary.id() == dictionary_id)
+        .ok_or(Error::MissingDictionary(dictionary_id))?;
+    Ok(Some(dictionary))
+}
@@ -0,0 +1,157 @@
+use crate::block::{BlockHeader, BlockType, MAX_BLOCK_SIZE};
+use crate::compressed_block::CompressedBlock;
+use crate::frame::{FrameHeader, MAGIC_NUMBER};
+use crate::match_finder::{MatchFinder, MatchFinderParameters, Strategy};
+use crate::xxhash::xxh64;
+
+/// Level used when none is given, a balance between speed and ratio.
+pub const DEFAULT_LEVEL: u8 = 3;
+/// Strongest level, the levels above it use its parameters.
+pub const MAX_LEVEL: u8 = 19;
+/// Smallest window a frame header can describe.
+const MIN_WINDOW_LOG: u8 = 10;
+
+/// Parameters of the levels 1 to `MAX_LEVEL`: window log, chain log, hash
+/// log, search log, min match, target length and strategy.
+#[rustfmt::skip]
+const LEVELS: [(u8, u8, u8, u8, u8, u32, Strategy); MAX_LEVEL as usize] = [
+    (19, 13, 14, 1, 7, 0, Strategy::Fast),
+    (20, 15, 16, 1, 6, 0, Strategy::Fast),
+    (21, 16, 17, 1, 5, 0, Strategy::DoubleFast),
+    (21, 18, 18, 1, 5, 0, Strategy::DoubleFast),
+    (21, 18, 19, 3, 5, 16, Strategy::Greedy),
+    (21, 18, 19, 3, 5, 24, Strategy::Lazy),
+    (21, 19, 20, 4, 5, 32, Strategy::Lazy),
+    (21, 19, 20, 4, 5, 48, Strategy::Lazy2),
+    (22, 20, 21, 4, 5, 64, Strategy::Lazy2),
+    (22, 21, 22, 5, 5, 96, Strategy::Lazy2),
+    (22, 21, 22, 6, 5, 128, Strategy::Lazy2),
+    (22, 22, 23, 6, 5, 128, Strategy::Lazy2),
+    (22, 22, 23, 7, 5, 192, Strategy::Lazy2),
+    (22, 23, 23, 7, 4, 256, Strategy::Lazy2),
+    (22, 23, 23, 8, 4, 384, Strategy::Lazy2),
+    (23, 23, 23, 8, 4, 512, Strategy::Lazy2),
+    (23, 24, 23, 9, 4, 768, Strategy::Lazy2),
+    (23, 24, 23, 10, 4, 1024, Strategy::Lazy2),
+    (23, 24, 24, 11, 4, 4096, Strategy::Lazy2),
+];
+
+/// Parameters of `level` for an input of `input_len` bytes: the window is
+/// shrunk to the input, and the tables to the window. Level 0 is the
+/// default level.
+pub fn level_parameters(level: u8, input_len: usize) -> MatchFinderParameters {
+    let level = match level {
+        0 => DEFAULT_LEVEL,
+        level => level.min(MAX_LEVEL),
+    };
+    let (window_log, chain_log, hash_log, search_log, min_match, target_length, strategy) =
+        LEVELS[level as usize - 1];
+
+    let input_log = input_len.max(1).next_power_of_two().ilog2() as u8;
+    let window_log = window_log.min(input_log.max(MIN_WINDOW_LOG));
+    MatchFinderParameters {
+        window_log,
+        chain_log: chain_log.min(window_log + 1),
+        hash_log: hash_log.min(window_log + 1),
+        search_log,
+        min_match,
+        target_length,
+        strategy,
+    }
+}
+
+/// Compresses `input` into a single frame, ended by a content checksum.
+pub fn encode_frame(input: &[u8], parameters: MatchFinderParameters) -> Vec<u8> {
+    let window_log = parameters.window_log;
+    let mut output = MAGIC_NUMBER.to_le_bytes().to_vec();
+    output.extend(FrameHeader::encode(input.len() as u64, window_log, true));
+
+    let mut encoder = BlockEncoder {
+        match_finder: MatchFinder::new(parameters),
+        repeated_offsets: [1, 4, 8],
+    };
+    let block_size = MAX_BLOCK_SIZE.min(1 << window_log);
+    let mut start = 0;
+    loop {
+        let end = input.len().min(start + block_size);
+        let is_last_block = end == input.len();
+        encoder.encode_block(input, start, end, is_last_block, &mut output);
+        if is_last_block {
+            break;
+        }
+        start = end;
+    }
+
+    output.extend((xxh64(input, 0) as u32).to_le_bytes());
+    output
+}
+
+/// State carried from one block to the next while encoding a frame.
+struct BlockEncoder {
+    match_finder: MatchFinder,
+    /// Repeated offsets as seen by the decoder after the previous blocks.
+    repeated_offsets: [u32; 3],
+}
+
+impl BlockEncoder {
+    /// Writes `input[start..end]` as an RLE block when it is a single byte
+    /// repeated, as a compressed block when it is smaller than the input, or
+    /// as a raw block.
+    fn encode_block(
+        &mut self,
+        input: &[u8],
+        start: usize,
+        end: usize,
+        is_last_block: bool,
+        output: &mut Vec<u8>,
+    ) {
+        let block = &input[start..end];
+        if block.len() > 1 && block.iter().all(|byte| *byte == block[0]) {
+            let header = BlockHeader::new(is_last_block, BlockType::Rle, block.len());
+            output.extend(header.to_bytes());
+            output.push(block[0]);
+            return;
+        }
+
+        let mut repeated_offsets = self.repeated_offsets;
+        let sequences = self
+            .match_finder
+            .find_sequences(input, start, end, &mut repeated_offsets);
+        let compressed = CompressedBlock::encode(&sequences.literals, &sequences.sequences);
+
+        if compressed.len() < block.len() {
+            let header = BlockHeader::new(is_last_block, BlockType::Compressed, compressed.len());
+            output.extend(header.to_bytes());
+            output.extend(compressed);
+            self.repeated_offsets = repeated_offsets;
+        } else {
+            let header = BlockHeader::new(is_last_block, BlockType::Raw, block.len());
+            output.extend(header.to_bytes());
+            output.extend_from_slice(block);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parameters_fit_the_input() {
+        let parameters = level_parameters(MAX_LEVEL, 1000);
+        assert_eq!(parameters.window_log, MIN_WINDOW_LOG);
+        assert_eq!(parameters.hash_log, MIN_WINDOW_LOG + 1);
+        assert_eq!(parameters.chain_log, MIN_WINDOW_LOG + 1);
+
+        let parameters = level_parameters(DEFAULT_LEVEL, 300_000);
+        assert_eq!(parameters.window_log, 19);
+        assert_eq!(parameters.strategy, Strategy::DoubleFast);
+
+        let parameters = level_parameters(1, 100 << 20);
+        assert_eq!(parameters, level_parameters(1, 1 << 19));
+        assert_eq!(
+            level_parameters(0, 5000),
+            level_parameters(DEFAULT_LEVEL, 5000)
+        );
+    }
+}
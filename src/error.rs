@@ -0,0 +1,62 @@
+use std::fmt;
+
+/// Reasons for which Zstandard data cannot be decoded.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Error {
+    /// A frame or dictionary doesn't start with the expected magic number.
+    InvalidMagic(u32),
+    /// A reserved bit or field of the named header is set.
+    ReservedBitSet(&'static str),
+    /// The input ends in the middle of the named structure.
+    Truncated(&'static str),
+    /// An entropy-coded stream or table doesn't decode to valid values.
+    CorruptBitstream(&'static str),
+    /// A structure is inconsistent with itself or with the previous blocks.
+    Corrupted(&'static str),
+    /// The content checksum stored in a frame doesn't match the decoded data.
+    ChecksumMismatch { expected: u32, actual: u32 },
+    /// The input relies on a feature that is not implemented.
+    Unsupported(&'static str),
+    /// The window size of a frame is bigger than what the decoder accepts.
+    WindowTooLarge(u64),
+    /// No dictionary with the ID required by a frame was provided.
+    MissingDictionary(u32),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::InvalidMagic(magic_number) => {
+                write!(f, "Invalid magic number {:08x}", magic_number)
+            }
+            Error::ReservedBitSet(header) => write!(f, "Reserved bit set in {}", header),
+            Error::Truncated(structure) => write!(f, "Truncated {}", structure),
+            Error::CorruptBitstream(reason) => write!(f, "Corrupt bitstream: {}", reason),
+            Error::Corrupted(reason) => write!(f, "Corrupted data: {}", reason),
+            Error::ChecksumMismatch { expected, actual } => write!(
+                f,
+                "Content checksum mismatch: expected {:08x}, got {:08x}",
+                expected, actual
+            ),
+            Error::Unsupported(feature) => write!(f, "Unsupported {}", feature),
+            Error::WindowTooLarge(window_size) => {
+                write!(f, "Window size {} is too large", window_size)
+            }
+            Error::MissingDictionary(id) => write!(f, "Missing dictionary with ID {}", id),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// Truncated input is reported as an unexpected end of file, other errors as
+/// invalid data. The original error can be retrieved with `get_ref`.
+impl From<Error> for std::io::Error {
+    fn from(error: Error) -> Self {
+        let kind = match error {
+            Error::Truncated(_) => std::io::ErrorKind::UnexpectedEof,
+            _ => std::io::ErrorKind::InvalidData,
+        };
+        std::io::Error::new(kind, error)
+    }
+}
@@ -1,9 +1,17 @@
 use log::debug;
 
 use crate::block::Block;
-use crate::Res;
+use crate::dictionary::Dictionary;
+use crate::fse::FseDecodingTable;
+use crate::huffman::HuffmanDecodingTable;
+use crate::xxhash::xxh64;
+use crate::{read_array, Error, Res};
 
 pub const MAGIC_NUMBER: u32 = 0xFD2FB528;
+pub const SKIPPABLE_MAGIC_NUMBER: u32 = 0x184D2A50;
+const SKIPPABLE_MAGIC_NUMBER_MASK: u32 = 0xFFFFFFF0;
+/// Largest window accepted, as with `--long=31` in the reference encoder.
+const MAX_WINDOW_SIZE: u64 = 1 << 31;
 
 #[derive(Debug)]
 pub struct Frame {
@@ -14,12 +22,12 @@ pub struct Frame {
 
 impl Frame {
     pub fn from_bytes(mut bytes: &[u8]) -> Res<Self> {
-        let magic_number = u32::from_le_bytes(bytes[0..4].try_into()?);
+        let magic_number = u32::from_le_bytes(read_array(bytes, "magic number")?);
         debug!("magic_number {:02x?}", &bytes[..4]);
         bytes = &bytes[4..];
 
         if magic_number != MAGIC_NUMBER {
-            return Err("Invalid magic number".into());
+            return Err(Error::InvalidMagic(magic_number));
         }
 
         let frame_header = FrameHeader::from_bytes(bytes)?;
@@ -38,9 +46,8 @@ impl Frame {
             }
         }
 
-        let content_checksum = if frame_header.frame_header_descriptor.content_checksum_flag() {
-            debug!("content_checksum {:02x?}", &bytes[..4]);
-            Some(u32::from_le_bytes(bytes[..4].try_into()?))
+        let content_checksum = if frame_header.has_content_checksum() {
+            Some(u32::from_le_bytes(read_array(bytes, "content checksum")?))
         } else {
             None
         };
@@ -70,11 +77,194 @@ impl Frame {
         4 + self.frame_header.len + data_blocks_len + content_checksum_len
     }
 
-    pub fn decode(self) -> Vec<u8> {
-        self.data_blocks
-            .into_iter()
-            .flat_map(|block| block.decode())
-            .collect()
+    /// Dictionary ID required to decode the frame, 0 if unspecified.
+    pub fn dictionary_id(&self) -> u32 {
+        self.frame_header.dictionary_id
+    }
+
+    pub fn decode(self, verify_checksum: bool, dictionary: Option<&Dictionary>) -> Res<Vec<u8>> {
+        let mut context = DecodingContext::new(self.frame_header.window_size);
+        if let Some(dictionary) = dictionary {
+            context.load_dictionary(dictionary);
+        }
+        let mut output = vec![];
+
+        for block in self.data_blocks {
+            output.extend(block.decode(&mut context)?);
+        }
+
+        if let Some(expected) = self.content_checksum
+            && verify_checksum
+        {
+            let actual = xxh64(&output, 0) as u32;
+            if actual != expected {
+                return Err(Error::ChecksumMismatch { expected, actual });
+            }
+        }
+
+        Ok(output)
+    }
+}
+
+/// Frame ignored by decoders, carrying user data such as seek tables.
+#[derive(Debug)]
+pub struct SkippableFrame {
+    magic_variant: u8,
+    user_data: Vec<u8>,
+    /// Position of the frame in the stream, counting the frames of both kinds.
+    pub(crate) index: usize,
+}
+
+impl SkippableFrame {
+    pub fn is_skippable(bytes: &[u8]) -> bool {
+        bytes.len() >= 4
+            && u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+                & SKIPPABLE_MAGIC_NUMBER_MASK
+                == SKIPPABLE_MAGIC_NUMBER
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Res<Self> {
+        let magic_number = u32::from_le_bytes(read_array(bytes, "skippable frame")?);
+        if !Self::is_skippable(bytes) {
+            return Err(Error::InvalidMagic(magic_number));
+        }
+        let magic_variant = bytes[0] & 0x0F;
+
+        let frame_size = u32::from_le_bytes(read_array(&bytes[4..], "skippable frame")?) as usize;
+        let user_data = bytes
+            .get(8..8 + frame_size)
+            .ok_or(Error::Truncated("skippable frame"))?
+            .to_vec();
+        debug!(
+            "skippable frame, magic_variant {}, frame_size {}",
+            magic_variant, frame_size
+        );
+
+        Ok(Self {
+            magic_variant,
+            user_data,
+            index: 0,
+        })
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        8 + self.user_data.len()
+    }
+
+    /// Lower 4 bits of the magic number, from 0 to 15.
+    pub fn magic_variant(&self) -> u8 {
+        self.magic_variant
+    }
+
+    pub fn user_data(&self) -> &[u8] {
+        &self.user_data
+    }
+
+    /// Position of the frame in the stream, among all the frames: a
+    /// skippable frame at index 2 follows two frames, skippable or not.
+    pub fn index(&self) -> usize {
+        self.index
+    }
+}
+
+/// State carried from one block to the next while decoding a frame.
+#[derive(Debug)]
+pub struct DecodingContext {
+    window_size: usize,
+    /// Length of the dictionary content that precedes the frame content.
+    dictionary_len: usize,
+    /// Number of bytes of the frame decoded by the previous blocks.
+    decoded_len: usize,
+    /// Last decoded bytes of the frame, preceded by the dictionary content
+    /// while it is reachable. Holds at least `reachable_len()` bytes.
+    pub history: Vec<u8>,
+    pub repeated_offsets: [u32; 3],
+    /// Huffman table of the last compressed literals section, reused by
+    /// treeless literals sections.
+    pub huffman_table: Option<HuffmanDecodingTable>,
+    /// Sequence decoding tables of the last compressed block, reused by the
+    /// repeat compression mode.
+    pub literals_length_table: Option<FseDecodingTable>,
+    pub offset_table: Option<FseDecodingTable>,
+    pub match_length_table: Option<FseDecodingTable>,
+}
+
+impl DecodingContext {
+    pub fn new(window_size: u64) -> Self {
+        Self {
+            window_size: window_size as usize,
+            dictionary_len: 0,
+            decoded_len: 0,
+            history: vec![],
+            repeated_offsets: [1, 4, 8],
+            huffman_table: None,
+            literals_length_table: None,
+            offset_table: None,
+            match_length_table: None,
+        }
+    }
+
+    /// Starts from the entropy tables, repeated offsets and content of the
+    /// dictionary instead of an empty state.
+    /// Matches can reach into the dictionary content as long as the frame
+    /// output is at most `window_size` bytes.
+    pub fn load_dictionary(&mut self, dictionary: &Dictionary) {
+        self.dictionary_len = dictionary.content.len();
+        self.history = dictionary.content.clone();
+        self.repeated_offsets = dictionary.repeated_offsets;
+
+        if let Some(tables) = &dictionary.entropy_tables {
+            self.huffman_table = Some(tables.huffman_table.clone());
+            self.literals_length_table = Some(tables.literals_length_table.clone());
+            self.offset_table = Some(tables.offset_table.clone());
+            self.match_length_table = Some(tables.match_length_table.clone());
+        }
+    }
+
+    /// Checks that a match `offset` bytes back from the end of the
+    /// `block_len` bytes already decoded in the current block stays within
+    /// the window. Offsets reaching before the frame content go into the
+    /// dictionary content, only while the frame output is at most
+    /// `window_size` bytes.
+    pub fn check_offset(&self, offset: usize, block_len: usize) -> Res<()> {
+        let decoded_len = self.decoded_len + block_len;
+        if offset <= decoded_len {
+            if offset > self.window_size {
+                return Err(Error::CorruptBitstream(
+                    "match offset is bigger than the window size",
+                ));
+            }
+        } else if decoded_len > self.window_size || offset > decoded_len + self.dictionary_len {
+            return Err(Error::CorruptBitstream(
+                "match offset is bigger than the decoded data",
+            ));
+        }
+        debug_assert!(offset <= block_len + self.history.len());
+        Ok(())
+    }
+
+    /// Number of bytes matches of the next block can reach before it: the
+    /// frame output and the dictionary content while the output fits in
+    /// the window, then the last `window_size` bytes.
+    fn reachable_len(&self) -> usize {
+        if self.decoded_len <= self.window_size {
+            self.decoded_len + self.dictionary_len
+        } else {
+            self.window_size
+        }
+    }
+
+    /// Appends the output of a block to the history. Bytes out of reach are
+    /// dropped once they make up half of the history, so that the history
+    /// is not shifted after every block.
+    pub fn extend_history(&mut self, output: &[u8]) {
+        self.decoded_len += output.len();
+        self.history.extend_from_slice(output);
+        let reachable_len = self.reachable_len();
+        if self.history.len() > reachable_len.saturating_mul(2) {
+            let excess = self.history.len() - reachable_len;
+            self.history.drain(..excess);
+        }
     }
 }
 
@@ -82,17 +272,12 @@ impl Frame {
 struct FrameHeaderDescriptor(u8);
 
 impl FrameHeaderDescriptor {
-    fn new(byte: u8) -> Self {
+    fn new(byte: u8) -> Res<Self> {
         let s = Self(byte);
-        assert!(
-            !s.unused_flag(),
-            "Unused bit in frame header descriptor is set"
-        );
-        assert!(
-            !s.reserved_flag(),
-            "Reserved bit in frame header descriptor is set"
-        );
-        s
+        if s.reserved_flag() {
+            return Err(Error::ReservedBitSet("frame header descriptor"));
+        }
+        Ok(s)
     }
 
     fn get_bit(&self, n: u8) -> bool {
@@ -116,10 +301,6 @@ impl FrameHeaderDescriptor {
         self.get_bit(5)
     }
 
-    fn unused_flag(&self) -> bool {
-        self.get_bit(4)
-    }
-
     fn reserved_flag(&self) -> bool {
         self.get_bit(3)
     }
@@ -159,24 +340,37 @@ impl WindowDescriptor {
 }
 
 #[derive(Debug)]
-struct FrameHeader {
+pub struct FrameHeader {
     frame_header_descriptor: FrameHeaderDescriptor,
-    window_size: u64,
-    dictionary_id: u32,
+    pub window_size: u64,
+    pub dictionary_id: u32,
+    #[allow(dead_code)]
     frame_content_size: u64,
-    len: usize,
+    pub len: usize,
 }
 
 impl FrameHeader {
-    fn from_bytes(bytes: &[u8]) -> Res<Self> {
-        let fhd = FrameHeaderDescriptor::new(bytes[0]);
+    /// Length of the frame header, known from its first byte, the frame
+    /// header descriptor.
+    pub fn len_from_descriptor(descriptor: u8) -> usize {
+        let fhd = FrameHeaderDescriptor(descriptor);
+        let window_descriptor_len = if fhd.single_segment_flag() { 0 } else { 1 };
+        1 + window_descriptor_len
+            + fhd.dictionary_id_field_size() as usize
+            + fhd.frame_content_size_field_size() as usize
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Res<Self> {
+        let [descriptor] = read_array(bytes, "frame header")?;
+        let fhd = FrameHeaderDescriptor::new(descriptor)?;
         let mut index = 1usize;
 
         let window_descriptor = if fhd.single_segment_flag() {
             None
         } else {
+            let [descriptor] = read_array(&bytes[index..], "frame header")?;
             index += 1;
-            Some(WindowDescriptor::new(bytes[1]))
+            Some(WindowDescriptor::new(descriptor))
         };
 
         let did_field_size = fhd.dictionary_id_field_size();
@@ -187,9 +381,13 @@ impl FrameHeader {
         let frame_content_size = Self::parse_frame_content_size(&bytes[index..], fcs_field_size)?;
         index += fcs_field_size as usize;
 
-        let window_size = window_descriptor
-            .map(|wd| wd.to_window_size())
-            .unwrap_or(frame_content_size);
+        let window_size = match window_descriptor {
+            Some(wd) => wd.to_window_size(),
+            None => frame_content_size,
+        };
+        if window_size > MAX_WINDOW_SIZE {
+            return Err(Error::WindowTooLarge(window_size));
+        }
 
         let frame_header = Self {
             frame_header_descriptor: fhd,
@@ -203,13 +401,47 @@ impl FrameHeader {
         Ok(frame_header)
     }
 
+    /// Writes the header of a frame of `frame_content_size` bytes, for an
+    /// encoder whose matches reach at most `1 << window_log` bytes back. The
+    /// window descriptor is replaced by the content size when the whole
+    /// frame fits in the window.
+    pub fn encode(frame_content_size: u64, window_log: u8, content_checksum: bool) -> Vec<u8> {
+        debug_assert!((10..=31).contains(&window_log));
+        let single_segment = frame_content_size <= 1 << window_log;
+
+        let (fcs_flag, fcs_bytes) = if single_segment && frame_content_size < 256 {
+            (0, vec![frame_content_size as u8])
+        } else if (256..65792).contains(&frame_content_size) {
+            let fcs = (frame_content_size - 256) as u16;
+            (1, fcs.to_le_bytes().to_vec())
+        } else if frame_content_size <= u32::MAX as u64 {
+            (2, (frame_content_size as u32).to_le_bytes().to_vec())
+        } else {
+            (3, frame_content_size.to_le_bytes().to_vec())
+        };
+
+        let descriptor =
+            fcs_flag << 6 | (single_segment as u8) << 5 | (content_checksum as u8) << 2;
+        let mut bytes = vec![descriptor];
+        if !single_segment {
+            bytes.push((window_log - 10) << 3);
+        }
+        bytes.extend(fcs_bytes);
+        debug_assert_eq!(bytes.len(), Self::len_from_descriptor(descriptor));
+        bytes
+    }
+
+    pub fn has_content_checksum(&self) -> bool {
+        self.frame_header_descriptor.content_checksum_flag()
+    }
+
     fn parse_dictionary_id(bytes: &[u8], field_size: u8) -> Res<u32> {
         let did = match field_size {
             0 => 0,
-            1 => u8::from_le_bytes(bytes[..1].try_into()?) as u32,
-            2 => u16::from_le_bytes(bytes[..2].try_into()?) as u32,
-            4 => u32::from_le_bytes(bytes[..4].try_into()?),
-            _ => return Err("Invalid dictionary id field size".into()),
+            1 => u8::from_le_bytes(read_array(bytes, "frame header")?) as u32,
+            2 => u16::from_le_bytes(read_array(bytes, "frame header")?) as u32,
+            4 => u32::from_le_bytes(read_array(bytes, "frame header")?),
+            _ => unreachable!(),
         };
         Ok(did)
     }
@@ -217,11 +449,11 @@ impl FrameHeader {
     fn parse_frame_content_size(bytes: &[u8], field_size: u8) -> Res<u64> {
         let fcs = match field_size {
             0 => 0,
-            1 => u8::from_le_bytes(bytes[..1].try_into()?) as u64,
-            2 => u16::from_le_bytes(bytes[..2].try_into()?) as u64 + 256,
-            4 => u32::from_le_bytes(bytes[..4].try_into()?) as u64,
-            8 => u64::from_le_bytes(bytes[..8].try_into()?),
-            _ => return Err("Invalid frame content size field size".into()),
+            1 => u8::from_le_bytes(read_array(bytes, "frame header")?) as u64,
+            2 => u16::from_le_bytes(read_array(bytes, "frame header")?) as u64 + 256,
+            4 => u32::from_le_bytes(read_array(bytes, "frame header")?) as u64,
+            8 => u64::from_le_bytes(read_array(bytes, "frame header")?),
+            _ => unreachable!(),
         };
         Ok(fcs)
     }
@@ -1,16 +1,28 @@
 use std::fmt::Debug;
 
-const LITERALS_LENGTH_DEFAULT_DISTRIBUTION: [i8; 36] = [
+use log::debug;
+
+use crate::{
+    bitstream::{BitWriter, Bitstream},
+    Error, Res,
+};
+
+/// Range of accuracy logs an FSE table description can encode and that any
+/// symbol type accepts.
+const MIN_ACCURACY_LOG: u8 = 5;
+const MAX_ACCURACY_LOG: u8 = 9;
+
+const LITERALS_LENGTH_DEFAULT_DISTRIBUTION: [i16; 36] = [
     4, 3, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 1, 1, 1, 2, 2, 2, 2, 2, 2, 2, 2, 2, 3, 2, 1, 1, 1, 1, 1,
     -1, -1, -1, -1,
 ];
 
-const MATCH_LENGTHS_DEFAULT_DISTRIBUTION: [i8; 53] = [
+const MATCH_LENGTHS_DEFAULT_DISTRIBUTION: [i16; 53] = [
     1, 4, 3, 2, 2, 2, 2, 2, 2, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1,
     1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, -1, -1, -1, -1, -1, -1, -1,
 ];
 
-const OFFSET_CODES_DEFAULT_DISTRIBUTION: [i8; 29] = [
+const OFFSET_CODES_DEFAULT_DISTRIBUTION: [i16; 29] = [
     1, 1, 1, 1, 1, 1, 2, 2, 2, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, -1, -1, -1, -1, -1,
 ];
 
@@ -18,7 +30,7 @@ const OFFSET_CODES_DEFAULT_DISTRIBUTION: [i8; 29] = [
 struct FseDecodingTableEntry {
     symbol: u8,
     num_bits: u8,
-    baseline: u8,
+    baseline: u16,
 }
 
 impl FseDecodingTableEntry {
@@ -31,7 +43,157 @@ impl FseDecodingTableEntry {
     }
 }
 
-#[derive(PartialEq, Eq)]
+/// Normalized probabilities of an FSE table, as sent in the table
+/// description before an FSE-compressed stream.
+#[derive(Debug)]
+pub struct FseTableDescription {
+    distribution: Vec<i16>,
+    accuracy_log: u8,
+    len: usize,
+}
+
+impl FseTableDescription {
+    pub fn from_bytes(bytes: &[u8], max_symbol: u8, max_accuracy_log: u8) -> Res<Self> {
+        let accuracy_log = (Self::read_bits(bytes, 0, 4) + 5) as u8;
+        if accuracy_log > max_accuracy_log {
+            return Err(Error::CorruptBitstream("FSE accuracy log is too big"));
+        }
+
+        let mut bit_offset = 4;
+        let mut remaining = (1i32 << accuracy_log) + 1;
+        let mut threshold = 1i32 << accuracy_log;
+        let mut num_bits = accuracy_log + 1;
+        let mut distribution = vec![];
+
+        while remaining > 1 {
+            if distribution.len() > max_symbol as usize {
+                return Err(Error::CorruptBitstream(
+                    "too many symbols in FSE table description",
+                ));
+            }
+
+            let max = (2 * threshold - 1) - remaining;
+            let bits = Self::read_bits(bytes, bit_offset, num_bits) as i32;
+            let mut count = if bits & (threshold - 1) < max {
+                bit_offset += num_bits as usize - 1;
+                bits & (threshold - 1)
+            } else {
+                bit_offset += num_bits as usize;
+                let count = bits & (2 * threshold - 1);
+                if count >= threshold {
+                    count - max
+                } else {
+                    count
+                }
+            };
+
+            count -= 1;
+            remaining -= count.abs();
+            distribution.push(count as i16);
+
+            if count == 0 {
+                loop {
+                    let repeat = Self::read_bits(bytes, bit_offset, 2);
+                    bit_offset += 2;
+                    distribution.extend(std::iter::repeat_n(0, repeat as usize));
+                    if repeat != 3 {
+                        break;
+                    }
+                }
+            }
+
+            while remaining < threshold {
+                num_bits -= 1;
+                threshold >>= 1;
+            }
+        }
+
+        if remaining != 1 || distribution.len() > max_symbol as usize + 1 {
+            return Err(Error::CorruptBitstream("invalid FSE table description"));
+        }
+
+        let len = bit_offset.div_ceil(8);
+        if len > bytes.len() {
+            return Err(Error::Truncated("FSE table description"));
+        }
+
+        debug!("fse_table_description {:02x?}", &bytes[..len]);
+        debug!(
+            "accuracy_log {}, distribution {:?}",
+            accuracy_log, distribution
+        );
+        Ok(Self {
+            distribution,
+            accuracy_log,
+            len,
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Writes the description of a distribution, whose trailing symbols have
+    /// a non-zero probability. Runs of zero probabilities are written as
+    /// repeat flags after the first zero.
+    pub fn encode(distribution: &[i16], accuracy_log: u8) -> Vec<u8> {
+        let mut writer = BitWriter::new();
+        writer.add_bits((accuracy_log - MIN_ACCURACY_LOG) as u64, 4);
+
+        let mut remaining = (1i32 << accuracy_log) + 1;
+        let mut threshold = 1i32 << accuracy_log;
+        let mut num_bits = accuracy_log + 1;
+        let mut symbol = 0;
+
+        while remaining > 1 {
+            let probability = distribution[symbol] as i32;
+            symbol += 1;
+
+            let max = (2 * threshold - 1) - remaining;
+            remaining -= probability.abs();
+            let mut count = probability + 1;
+            if count >= threshold {
+                count += max;
+            }
+            // Small counts are written without their highest bit, always 0.
+            let count_bits = if count < max { num_bits - 1 } else { num_bits };
+            writer.add_bits(count as u64, count_bits);
+
+            if probability == 0 {
+                let zeros = distribution[symbol..]
+                    .iter()
+                    .take_while(|n| **n == 0)
+                    .count();
+                symbol += zeros;
+                for _ in 0..zeros / 3 {
+                    writer.add_bits(3, 2);
+                }
+                writer.add_bits((zeros % 3) as u64, 2);
+            }
+
+            while remaining < threshold {
+                num_bits -= 1;
+                threshold >>= 1;
+            }
+        }
+
+        writer.into_bytes()
+    }
+
+    /// Reads `n` bits of a little-endian forward bitstream, starting at
+    /// `bit_offset`. Bits past the end of `bytes` are read as zeroes.
+    fn read_bits(bytes: &[u8], bit_offset: usize, n: u8) -> u32 {
+        let start = bit_offset / 8;
+        let mut container = [0u8; 4];
+        for (i, byte) in container.iter_mut().enumerate() {
+            *byte = bytes.get(start + i).copied().unwrap_or(0);
+        }
+        let container = u32::from_le_bytes(container) >> (bit_offset % 8);
+        container & ((1 << n) - 1)
+    }
+}
+
+#[derive(Clone, PartialEq, Eq)]
 pub struct FseDecodingTable {
     entries: Vec<FseDecodingTableEntry>,
     accuracy_log: u8,
@@ -45,7 +207,7 @@ impl FseDecodingTable {
             .map(|row| FseDecodingTableEntry {
                 symbol: row.0,
                 num_bits: row.1,
-                baseline: row.2,
+                baseline: row.2 as u16,
             })
             .collect();
 
@@ -58,41 +220,42 @@ impl FseDecodingTable {
         }
     }
 
-    pub fn from_distribution(distribution: &[i8]) -> Self {
-        let accuracy_log = (distribution.len().ilog2() + 1) as u8;
-        let table_size = 1 << accuracy_log;
-        let mut symbols = vec![None; table_size];
+    pub fn from_description(description: &FseTableDescription) -> Res<Self> {
+        Self::from_distribution(&description.distribution, description.accuracy_log)
+    }
 
-        let mut last_index = symbols.len() - 1;
-        for (i, n) in distribution.iter().enumerate() {
-            if *n == -1 {
-                symbols[last_index] = Some(i as u8);
-                last_index -= 1;
-            }
+    /// Builds the table of a normalized distribution, where -1 stands for a
+    /// "less than 1" probability taking a single cell, and the cells of all
+    /// the symbols add up to exactly `1 << accuracy_log`.
+    pub fn from_distribution(distribution: &[i16], accuracy_log: u8) -> Res<Self> {
+        if !(MIN_ACCURACY_LOG..=MAX_ACCURACY_LOG).contains(&accuracy_log) {
+            return Err(Error::CorruptBitstream("FSE accuracy log is out of range"));
         }
-
-        let mut position = 0;
-        for (i, n) in distribution.iter().enumerate() {
-            if *n == -1 {
-                continue;
-            }
-
-            let mut cells_allocated = 0;
-            while cells_allocated < *n {
-                if symbols[position].is_none() {
-                    symbols[position] = Some(i as u8);
-                    cells_allocated += 1;
-                }
-
-                position += (table_size >> 1) + (table_size >> 3) + 3;
-                position &= table_size - 1;
-            }
+        if distribution.len() > 256 || distribution.iter().any(|n| *n < -1) {
+            return Err(Error::CorruptBitstream("invalid FSE distribution"));
+        }
+        let cells = distribution
+            .iter()
+            .map(|n| n.unsigned_abs() as usize)
+            .sum::<usize>();
+        if cells != 1 << accuracy_log {
+            return Err(Error::CorruptBitstream(
+                "FSE distribution doesn't fill the table",
+            ));
         }
 
-        let symbols = symbols.into_iter().map(|s| s.unwrap()).collect::<Vec<_>>();
+        Ok(Self::from_valid_distribution(distribution, accuracy_log))
+    }
+
+    /// Builds the table of a distribution already known to be valid, such as
+    /// the predefined ones.
+    fn from_valid_distribution(distribution: &[i16], accuracy_log: u8) -> Self {
+        let table_size = 1 << accuracy_log;
+        let symbols = spread_symbols(distribution, accuracy_log);
         let mut entries = vec![FseDecodingTableEntry::new(); table_size];
 
-        for symbol in 0..distribution.len() as u8 {
+        for symbol in 0..distribution.len() {
+            let symbol = symbol as u8;
             let indices = symbols
                 .iter()
                 .enumerate()
@@ -100,6 +263,9 @@ impl FseDecodingTable {
                 .collect::<Vec<_>>();
 
             let probability = indices.len();
+            if probability == 0 {
+                continue;
+            }
             if probability == 1 {
                 let entry = FseDecodingTableEntry {
                     symbol,
@@ -110,26 +276,30 @@ impl FseDecodingTable {
                 continue;
             }
 
-            let next_power_of_2 = 1 << (probability.ilog2() + 1);
+            // The first states read one more bit than the others, unless the
+            // probability is a power of 2. A symbol with the whole table as
+            // probability reads no bits at all.
+            let high_bit = probability.ilog2();
+            let next_power_of_2 = 1 << (high_bit + 1);
             let doubles = next_power_of_2 - probability;
-            let width_size = (table_size / next_power_of_2).ilog2();
+            let double_num_bits = accuracy_log as u32 - high_bit;
 
             for (i, index) in indices.iter().enumerate() {
                 let num_bits = if i < doubles {
-                    width_size + 1
+                    double_num_bits
                 } else {
-                    width_size
+                    double_num_bits - 1
                 };
                 let baseline = if i < doubles {
-                    (probability - doubles) * (1 << width_size) + i * (1 << (width_size + 1))
+                    (((probability - doubles) << double_num_bits) >> 1) + (i << double_num_bits)
                 } else {
-                    (i - doubles) * (1 << width_size)
+                    (i - doubles) << (double_num_bits - 1)
                 };
 
                 let entry = FseDecodingTableEntry {
                     symbol,
                     num_bits: num_bits as u8,
-                    baseline: baseline as u8,
+                    baseline: baseline as u16,
                 };
                 entries[*index] = entry;
             }
@@ -142,16 +312,29 @@ impl FseDecodingTable {
         }
     }
 
+    /// Table of accuracy log 0 which always decodes `symbol` without
+    /// reading any bit.
+    pub fn rle(symbol: u8) -> Self {
+        Self {
+            entries: vec![FseDecodingTableEntry {
+                symbol,
+                num_bits: 0,
+                baseline: 0,
+            }],
+            accuracy_log: 0,
+        }
+    }
+
     pub fn literals_length_default_distribution() -> Self {
-        Self::from_distribution(&LITERALS_LENGTH_DEFAULT_DISTRIBUTION)
+        Self::from_valid_distribution(&LITERALS_LENGTH_DEFAULT_DISTRIBUTION, 6)
     }
 
     pub fn match_lengths_default_distribution() -> Self {
-        Self::from_distribution(&MATCH_LENGTHS_DEFAULT_DISTRIBUTION)
+        Self::from_valid_distribution(&MATCH_LENGTHS_DEFAULT_DISTRIBUTION, 6)
     }
 
     pub fn offset_codes_default_distribution() -> Self {
-        Self::from_distribution(&OFFSET_CODES_DEFAULT_DISTRIBUTION)
+        Self::from_valid_distribution(&OFFSET_CODES_DEFAULT_DISTRIBUTION, 5)
     }
 
     pub fn accuracy_log(&self) -> u8 {
@@ -159,6 +342,41 @@ impl FseDecodingTable {
     }
 }
 
+/// Symbol of every state of a table, the "less than 1" probabilities taking
+/// the last states and the others being spread over the table.
+fn spread_symbols(distribution: &[i16], accuracy_log: u8) -> Vec<u8> {
+    let table_size = 1 << accuracy_log;
+    let mut symbols = vec![None; table_size];
+
+    let mut high_threshold = table_size;
+    for (i, n) in distribution.iter().enumerate() {
+        if *n == -1 {
+            high_threshold -= 1;
+            symbols[high_threshold] = Some(i as u8);
+        }
+    }
+
+    let mut position = 0;
+    for (i, n) in distribution.iter().enumerate() {
+        if *n == -1 {
+            continue;
+        }
+
+        let mut cells_allocated = 0;
+        while cells_allocated < *n {
+            if symbols[position].is_none() {
+                symbols[position] = Some(i as u8);
+                cells_allocated += 1;
+            }
+
+            position += (table_size >> 1) + (table_size >> 3) + 3;
+            position &= table_size - 1;
+        }
+    }
+
+    symbols.into_iter().map(|s| s.unwrap()).collect()
+}
+
 impl Debug for FseDecodingTable {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.debug_struct("FseDecodingTable")
@@ -179,16 +397,16 @@ impl Debug for FseDecodingTable {
 #[derive(Debug)]
 pub struct FseDecoder {
     table: FseDecodingTable,
-    state: u8,
+    state: u16,
 }
 
 impl FseDecoder {
-    pub fn new(table: FseDecodingTable, state: u8) -> Self {
+    pub fn new(table: FseDecodingTable, state: u16) -> Self {
         Self { table, state }
     }
 
-    pub fn set_state(&mut self, state: u8) {
-        self.state = state % self.table.entries.len() as u8;
+    pub fn set_state(&mut self, state: u16) {
+        self.state = state;
     }
 
     pub fn symbol(&self) -> u8 {
@@ -199,14 +417,388 @@ impl FseDecoder {
         self.table.entries[self.state as usize].num_bits
     }
 
-    pub fn baseline(&self) -> u8 {
+    pub fn baseline(&self) -> u16 {
         self.table.entries[self.state as usize].baseline
     }
+
+    pub fn update_state(&mut self, bs: &mut Bitstream) {
+        let state = self.baseline() + bs.get_bits(self.num_bits()) as u16;
+        self.set_state(state);
+    }
+}
+
+/// How the encoder moves from a state to the next one when encoding a
+/// symbol, derived from the probability of the symbol.
+#[derive(Clone, Copy, Debug)]
+struct SymbolTransform {
+    /// Number of bits to write, once added to the state, in the upper 16
+    /// bits.
+    delta_num_bits: u32,
+    /// Index of the first next state of the symbol in `states`, minus the
+    /// smallest state shifted by the number of bits written.
+    delta_find_state: i32,
+}
+
+/// Encoding counterpart of `FseDecodingTable`, built from the same
+/// distribution so that its streams decode with it.
+#[derive(Debug)]
+pub struct FseEncodingTable {
+    distribution: Vec<i16>,
+    accuracy_log: u8,
+    /// States of the table offset by the table size, grouped by symbol.
+    states: Vec<u16>,
+    symbols: Vec<SymbolTransform>,
+}
+
+impl FseEncodingTable {
+    /// Builds the table of a distribution valid for `FseDecodingTable`, such
+    /// as the ones produced by the encoder.
+    pub fn from_distribution(distribution: &[i16], accuracy_log: u8) -> Self {
+        let table_size = 1usize << accuracy_log;
+        let accuracy_log_bits = (accuracy_log as u32) << 16;
+
+        let mut cumulative = Vec::with_capacity(distribution.len());
+        let mut total = 0;
+        for n in distribution {
+            cumulative.push(total);
+            total += n.unsigned_abs() as usize;
+        }
+        debug_assert_eq!(total, table_size);
+
+        let mut next = cumulative.clone();
+        let mut states = vec![0; table_size];
+        for (state, symbol) in spread_symbols(distribution, accuracy_log)
+            .into_iter()
+            .enumerate()
+        {
+            states[next[symbol as usize]] = (table_size + state) as u16;
+            next[symbol as usize] += 1;
+        }
+
+        let symbols = distribution
+            .iter()
+            .zip(cumulative)
+            .map(|(n, cumulative)| match *n {
+                0 => SymbolTransform {
+                    delta_num_bits: accuracy_log_bits + (1 << 16) - table_size as u32,
+                    delta_find_state: 0,
+                },
+                -1 | 1 => SymbolTransform {
+                    delta_num_bits: accuracy_log_bits - table_size as u32,
+                    delta_find_state: cumulative as i32 - 1,
+                },
+                n => {
+                    let max_num_bits = accuracy_log as u32 - (n as u32 - 1).ilog2();
+                    let min_state = (n as u32) << max_num_bits;
+                    SymbolTransform {
+                        delta_num_bits: (max_num_bits << 16) - min_state,
+                        delta_find_state: cumulative as i32 - n as i32,
+                    }
+                }
+            })
+            .collect();
+
+        Self {
+            distribution: distribution.to_vec(),
+            accuracy_log,
+            states,
+            symbols,
+        }
+    }
+
+    /// Builds a table fitted to the number of occurrences of each symbol,
+    /// with the accuracy log best suited to their total, up to
+    /// `max_accuracy_log`.
+    pub fn from_counts(counts: &[u32], max_accuracy_log: u8) -> Self {
+        let accuracy_log = optimal_accuracy_log(counts, max_accuracy_log);
+        Self::from_distribution(&normalize_counts(counts, accuracy_log), accuracy_log)
+    }
+
+    /// Table of accuracy log 0 which always encodes `symbol` without writing
+    /// any bit.
+    pub fn rle(symbol: u8) -> Self {
+        let mut distribution = vec![0; symbol as usize + 1];
+        distribution[symbol as usize] = 1;
+        let transform = SymbolTransform {
+            delta_num_bits: 0,
+            delta_find_state: 0,
+        };
+        Self {
+            distribution,
+            accuracy_log: 0,
+            states: vec![0],
+            symbols: vec![transform; symbol as usize + 1],
+        }
+    }
+
+    /// Description of the table, to send before the stream.
+    pub fn description(&self) -> Vec<u8> {
+        FseTableDescription::encode(&self.distribution, self.accuracy_log)
+    }
+
+    /// Approximate number of bits taken by the symbols counted in `counts`,
+    /// None if one of them cannot be encoded with the table.
+    pub fn estimated_bits(&self, counts: &[u32]) -> Option<f64> {
+        let mut bits = 0.0;
+        for (symbol, count) in counts.iter().enumerate() {
+            if *count == 0 {
+                continue;
+            }
+            let probability = self.distribution.get(symbol).copied().unwrap_or(0);
+            if probability == 0 {
+                return None;
+            }
+            let cells = probability.unsigned_abs() as f64;
+            bits += *count as f64 * (self.accuracy_log as f64 - cells.log2());
+        }
+        Some(bits)
+    }
+
+    pub fn literals_length_default_distribution() -> Self {
+        Self::from_distribution(&LITERALS_LENGTH_DEFAULT_DISTRIBUTION, 6)
+    }
+
+    pub fn match_lengths_default_distribution() -> Self {
+        Self::from_distribution(&MATCH_LENGTHS_DEFAULT_DISTRIBUTION, 6)
+    }
+
+    pub fn offset_codes_default_distribution() -> Self {
+        Self::from_distribution(&OFFSET_CODES_DEFAULT_DISTRIBUTION, 5)
+    }
+
+    /// Next state after encoding `symbol` from a state whose `num_bits`
+    /// lowest bits were written.
+    fn next_state(&self, symbol: u8, shifted_state: u32) -> u32 {
+        let transform = self.symbols[symbol as usize];
+        let index = shifted_state as i32 + transform.delta_find_state;
+        self.states[index as usize] as u32
+    }
+}
+
+/// Smallest accuracy log describing `counts` accurately, the table should
+/// be small enough for its description to be worth it with few symbols to
+/// encode, and big enough to give every symbol a cell.
+fn optimal_accuracy_log(counts: &[u32], max_accuracy_log: u8) -> u8 {
+    let total = counts.iter().sum::<u32>().max(2);
+    let max_symbol = counts.iter().rposition(|count| *count > 0).unwrap_or(0) as u32;
+
+    let max_bits_source = (total - 1).ilog2().saturating_sub(2) as u8;
+    let min_bits = (total.ilog2() + 1).min(max_symbol.max(1).ilog2() + 2) as u8;
+    max_bits_source
+        .min(max_accuracy_log)
+        .max(min_bits)
+        .clamp(MIN_ACCURACY_LOG, max_accuracy_log)
+}
+
+/// Scales the number of occurrences of each symbol into a distribution
+/// filling a table of the given accuracy log. Rare symbols get a "less than
+/// 1" probability, the rounding errors are then given to, or taken from, the
+/// symbols for which it changes the encoded size the least.
+fn normalize_counts(counts: &[u32], accuracy_log: u8) -> Vec<i16> {
+    let table_size = 1u64 << accuracy_log;
+    let total = counts.iter().map(|count| *count as u64).sum::<u64>();
+    let len = counts.iter().rposition(|count| *count > 0).unwrap() + 1;
+
+    let mut distribution = counts[..len]
+        .iter()
+        .map(|count| {
+            let count = *count as u64;
+            if count == 0 {
+                0
+            } else if count * table_size < total {
+                -1
+            } else {
+                ((count * table_size + total / 2) / total).max(1) as i16
+            }
+        })
+        .collect::<Vec<_>>();
+
+    let cells = distribution
+        .iter()
+        .map(|n| n.unsigned_abs() as i64)
+        .sum::<i64>();
+    let mut difference = table_size as i64 - cells;
+    // Bits saved by one more cell for a symbol, or lost by one less.
+    let gain = |symbol: usize, probability: i16| {
+        counts[symbol] as f64 * ((probability + 1) as f64 / probability as f64).log2()
+    };
+    while difference != 0 {
+        let adjustable = (0..len).filter(|s| {
+            let n = distribution[*s];
+            if difference > 0 {
+                n > 0
+            } else {
+                n > 1
+            }
+        });
+        let symbol = if difference > 0 {
+            adjustable
+                .max_by(|a, b| gain(*a, distribution[*a]).total_cmp(&gain(*b, distribution[*b])))
+        } else {
+            adjustable.min_by(|a, b| {
+                gain(*a, distribution[*a] - 1).total_cmp(&gain(*b, distribution[*b] - 1))
+            })
+        }
+        .expect("the table is big enough for every symbol");
+
+        let step = difference.signum();
+        distribution[symbol] += step as i16;
+        difference -= step;
+    }
+
+    distribution
+}
+
+/// Encodes symbols into a backward bitstream, in the reverse order of their
+/// decoding: the first symbol encoded is the last one decoded.
+pub struct FseEncoder<'a> {
+    table: &'a FseEncodingTable,
+    /// Current state, offset by the table size.
+    state: u32,
+}
+
+impl<'a> FseEncoder<'a> {
+    /// Starts from the state which decodes to `symbol`, without writing any
+    /// bit.
+    pub fn new(table: &'a FseEncodingTable, symbol: u8) -> Self {
+        let transform = table.symbols[symbol as usize];
+        let num_bits = (transform.delta_num_bits + (1 << 15)) >> 16;
+        let state = (num_bits << 16) - transform.delta_num_bits;
+        Self {
+            table,
+            state: table.next_state(symbol, state >> num_bits),
+        }
+    }
+
+    /// Writes the bits leading from the state of `symbol` to the current
+    /// state, then moves to the state of `symbol`.
+    pub fn encode(&mut self, writer: &mut BitWriter, symbol: u8) {
+        let transform = self.table.symbols[symbol as usize];
+        let num_bits = (self.state + transform.delta_num_bits) >> 16;
+        writer.add_bits(self.state as u64, num_bits as u8);
+        self.state = self.table.next_state(symbol, self.state >> num_bits);
+    }
+
+    /// Writes the current state, the initial state of the decoder.
+    pub fn flush(self, writer: &mut BitWriter) {
+        writer.add_bits(self.state as u64, self.table.accuracy_log);
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::test_rng::TestRng;
+
+    /// Encodes `symbols` then decodes them with the decoding table of the
+    /// same distribution.
+    fn round_trip(distribution: &[i16], accuracy_log: u8, symbols: &[u8]) {
+        let encoding_table = FseEncodingTable::from_distribution(distribution, accuracy_log);
+        let decoding_table = FseDecodingTable::from_distribution(distribution, accuracy_log);
+
+        let mut writer = BitWriter::new();
+        let (last, rest) = symbols.split_last().unwrap();
+        let mut encoder = FseEncoder::new(&encoding_table, *last);
+        for symbol in rest.iter().rev() {
+            encoder.encode(&mut writer, *symbol);
+        }
+        encoder.flush(&mut writer);
+        let bytes = writer.close();
+
+        let mut bs = Bitstream::new(&bytes).unwrap();
+        let state = bs.get_bits(accuracy_log) as u16;
+        let mut decoder = FseDecoder::new(decoding_table.unwrap(), state);
+        for (i, symbol) in symbols.iter().enumerate() {
+            assert_eq!(decoder.symbol(), *symbol, "symbol {}", i);
+            if i != symbols.len() - 1 {
+                decoder.update_state(&mut bs);
+            }
+        }
+        bs.check_fully_consumed().unwrap();
+    }
+
+    /// Pseudo-random symbols among those with a probability in `distribution`.
+    fn symbols(distribution: &[i16], len: usize) -> Vec<u8> {
+        let present = (0..distribution.len())
+            .filter(|s| distribution[*s] != 0)
+            .map(|s| s as u8)
+            .collect::<Vec<_>>();
+        let mut rng = TestRng::new();
+        (0..len)
+            .map(|_| present[rng.next_u64() as usize % present.len()])
+            .collect()
+    }
+
+    #[test]
+    fn encode_default_distributions() {
+        for (distribution, accuracy_log) in [
+            (&LITERALS_LENGTH_DEFAULT_DISTRIBUTION[..], 6),
+            (&MATCH_LENGTHS_DEFAULT_DISTRIBUTION[..], 6),
+            (&OFFSET_CODES_DEFAULT_DISTRIBUTION[..], 5),
+        ] {
+            round_trip(distribution, accuracy_log, &symbols(distribution, 1000));
+            round_trip(distribution, accuracy_log, &[3]);
+        }
+    }
+
+    #[test]
+    fn encode_custom_distributions() {
+        let distribution = [100, -1, 0, 3, 200, 1, 0, 0, 150, 57];
+        round_trip(&distribution, 9, &symbols(&distribution, 5000));
+        round_trip(&[0, 32], 5, &[1; 100]);
+        round_trip(&[-1; 32], 5, &symbols(&[-1; 32], 100));
+    }
+
+    #[test]
+    fn encode_table_descriptions() {
+        let mut long_zero_run = vec![20, 0];
+        long_zero_run.extend([0; 26]);
+        long_zero_run.extend([-1, 11]);
+        for (distribution, accuracy_log) in [
+            (&LITERALS_LENGTH_DEFAULT_DISTRIBUTION[..], 6),
+            (&MATCH_LENGTHS_DEFAULT_DISTRIBUTION[..], 6),
+            (&OFFSET_CODES_DEFAULT_DISTRIBUTION[..], 5),
+            (&[100, -1, 0, 3, 200, 1, 0, 0, 150, 57][..], 9),
+            (&[0, 0, 0, 0, 31, -1][..], 5),
+            (&long_zero_run[..], 5),
+        ] {
+            let bytes = FseTableDescription::encode(distribution, accuracy_log);
+            let description = FseTableDescription::from_bytes(&bytes, 255, 9).unwrap();
+            assert_eq!(description.distribution, distribution);
+            assert_eq!(description.accuracy_log, accuracy_log);
+            assert_eq!(description.len(), bytes.len());
+        }
+    }
+
+    #[test]
+    fn normalize_counts() {
+        let counts = [1000, 1, 0, 37, 0, 0, 0, 5, 200, 3];
+        for accuracy_log in MIN_ACCURACY_LOG..=MAX_ACCURACY_LOG {
+            let distribution = super::normalize_counts(&counts, accuracy_log);
+            assert_eq!(distribution.len(), counts.len());
+            let cells = distribution.iter().map(|n| n.abs()).sum::<i16>();
+            assert_eq!(cells, 1 << accuracy_log);
+            for (count, probability) in counts.iter().zip(&distribution) {
+                assert_eq!(*count == 0, *probability == 0);
+            }
+            FseDecodingTable::from_distribution(&distribution, accuracy_log).unwrap();
+        }
+    }
+
+    #[test]
+    fn encode_from_counts() {
+        let mut rng = TestRng::new();
+        let symbols = (0..3000).map(|_| rng.geometric(8) * 3).collect::<Vec<_>>();
+        let mut counts = [0; 256];
+        for symbol in &symbols {
+            counts[*symbol as usize] += 1;
+        }
+
+        let table = FseEncodingTable::from_counts(&counts, 9);
+        assert!(table.estimated_bits(&counts).unwrap() < 2.0 * symbols.len() as f64);
+        assert_eq!(table.estimated_bits(&[0, 1]), None);
+        round_trip(&table.distribution, table.accuracy_log, &symbols);
+    }
 
     const EXPECTED_LITERALS_LENGTH_CODE_TABLE: [(u8, u8, u8); 65] = [
         (0, 0, 0),
@@ -380,6 +972,153 @@ mod tests {
         (24, 5, 0),
     ];
 
+    #[test]
+    fn table_description() {
+        // Accuracy log 5, probabilities 18, 6, 4, -1, 0, 0, 0, 3 encoded by
+        // hand following the RFC.
+        let bits = [
+            (0, 4),
+            (19, 5),
+            (7, 4),
+            (5, 3),
+            (0, 2),
+            (1, 2),
+            (2, 2),
+            (7, 3),
+        ];
+        let mut value = 0u32;
+        let mut bit_offset = 0;
+        for (bits, n) in bits {
+            value |= bits << bit_offset;
+            bit_offset += n;
+        }
+        let bytes = value.to_le_bytes();
+
+        let description = FseTableDescription::from_bytes(&bytes, 255, 6).unwrap();
+        assert_eq!(description.accuracy_log, 5);
+        assert_eq!(description.distribution, [18, 6, 4, -1, 0, 0, 0, 3]);
+        assert_eq!(description.len(), 4);
+
+        let table = FseDecodingTable::from_description(&description).unwrap();
+        assert_eq!(table.entries.len(), 32);
+        assert_eq!(table.entries[31].symbol, 3);
+        for symbol in 4..7 {
+            assert!(table.entries.iter().all(|e| e.symbol != symbol));
+        }
+    }
+
+    #[test]
+    fn single_symbol_with_full_probability() {
+        let table = FseDecodingTable::from_distribution(&[0, 32], 5).unwrap();
+        for (i, entry) in table.entries.iter().enumerate() {
+            assert_eq!((entry.symbol, entry.num_bits), (1, 0));
+            assert_eq!(entry.baseline as usize, i);
+        }
+    }
+
+    #[test]
+    fn only_less_than_one_probabilities() {
+        let table = FseDecodingTable::from_distribution(&[-1; 32], 5).unwrap();
+        for (i, entry) in table.entries.iter().enumerate() {
+            assert_eq!(entry.symbol as usize, 31 - i);
+            assert_eq!((entry.num_bits, entry.baseline), (5, 0));
+        }
+    }
+
+    /// Checks that the states of every symbol split the table in ranges of
+    /// next states which don't overlap and cover it entirely.
+    fn check_state_ranges(table: &FseDecodingTable, distribution: &[i16]) {
+        let table_size = table.entries.len();
+        for (symbol, probability) in distribution.iter().enumerate() {
+            let mut ranges = table
+                .entries
+                .iter()
+                .filter(|e| e.symbol as usize == symbol)
+                .map(|e| (e.baseline as usize, e.baseline as usize + (1 << e.num_bits)))
+                .collect::<Vec<_>>();
+            assert_eq!(ranges.len(), probability.unsigned_abs() as usize);
+            if ranges.is_empty() {
+                continue;
+            }
+
+            ranges.sort();
+            assert_eq!(ranges[0].0, 0);
+            assert_eq!(ranges[ranges.len() - 1].1, table_size);
+            for pair in ranges.windows(2) {
+                assert_eq!(pair[0].1, pair[1].0);
+            }
+        }
+    }
+
+    #[test]
+    fn custom_distributions() {
+        for accuracy_log in MIN_ACCURACY_LOG..=MAX_ACCURACY_LOG {
+            let table_size = 1i16 << accuracy_log;
+            let mut distribution: Vec<i16> = vec![-1, 0, 0, 1, 2, -1, 0, 3, 1, -1];
+            let used = distribution.iter().map(|n| n.abs()).sum::<i16>();
+            distribution.push(table_size / 2 - used);
+            distribution.push(table_size / 4 - 1);
+            distribution.extend([1, -1, 0, 1]);
+            distribution.push(table_size / 4 - 2);
+
+            let table = FseDecodingTable::from_distribution(&distribution, accuracy_log).unwrap();
+            assert_eq!(table.accuracy_log(), accuracy_log);
+            assert_eq!(table.entries.len(), table_size as usize);
+            check_state_ranges(&table, &distribution);
+
+            let mut decoder = FseDecoder::new(table, 0);
+            // The first "less than 1" symbol takes the last state.
+            decoder.set_state(table_size as u16 - 1);
+            assert_eq!((decoder.symbol(), decoder.num_bits()), (0, accuracy_log));
+        }
+    }
+
+    #[test]
+    fn all_byte_symbols() {
+        let mut distribution = vec![1; 256];
+        distribution[255] = 257;
+        let table = FseDecodingTable::from_distribution(&distribution, 9).unwrap();
+        check_state_ranges(&table, &distribution);
+        round_trip(&distribution, 9, &symbols(&distribution, 1000));
+    }
+
+    #[test]
+    fn default_distributions() {
+        for (table, distribution) in [
+            (
+                FseDecodingTable::literals_length_default_distribution(),
+                &LITERALS_LENGTH_DEFAULT_DISTRIBUTION[..],
+            ),
+            (
+                FseDecodingTable::match_lengths_default_distribution(),
+                &MATCH_LENGTHS_DEFAULT_DISTRIBUTION[..],
+            ),
+            (
+                FseDecodingTable::offset_codes_default_distribution(),
+                &OFFSET_CODES_DEFAULT_DISTRIBUTION[..],
+            ),
+        ] {
+            let checked = FseDecodingTable::from_distribution(distribution, table.accuracy_log());
+            assert_eq!(checked.unwrap(), table);
+            check_state_ranges(&table, distribution);
+        }
+    }
+
+    #[test]
+    fn invalid_distributions() {
+        let invalid = [
+            (vec![16; 2], 4),
+            (vec![512; 2], 10),
+            (vec![16, 15], 5),
+            (vec![16, 17], 5),
+            (vec![-2, 16, 18], 5),
+            (vec![0; 300], 5),
+        ];
+        for (distribution, accuracy_log) in invalid {
+            assert!(FseDecodingTable::from_distribution(&distribution, accuracy_log).is_err());
+        }
+    }
+
     #[test]
     fn literals_length_code_table() {
         let actual = FseDecodingTable::literals_length_default_distribution();
@@ -0,0 +1,483 @@
+use log::debug;
+
+use crate::{
+    bitstream::{BitWriter, Bitstream},
+    fse::{FseDecoder, FseDecodingTable, FseEncoder, FseEncodingTable, FseTableDescription},
+    Error, Res,
+};
+
+const MAX_NUM_BITS: u8 = 11;
+const MAX_WEIGHTS_ACCURACY_LOG: u8 = 6;
+
+#[derive(Debug)]
+pub struct HuffmanTreeDescription {
+    weights: Vec<u8>,
+    len: usize,
+}
+
+impl HuffmanTreeDescription {
+    pub fn from_bytes(bytes: &[u8]) -> Res<Self> {
+        let header = *bytes
+            .first()
+            .ok_or(Error::Truncated("Huffman tree description"))?;
+
+        let description = if header < 128 {
+            let compressed_size = header as usize;
+            let compressed_weights = bytes
+                .get(1..1 + compressed_size)
+                .ok_or(Error::Truncated("Huffman tree description"))?;
+            let weights = Self::decode_fse_weights(compressed_weights)?;
+            Self {
+                weights,
+                len: 1 + compressed_size,
+            }
+        } else {
+            let number_of_symbols = (header - 127) as usize;
+            let weights_len = number_of_symbols.div_ceil(2);
+            let weights = bytes
+                .get(1..1 + weights_len)
+                .ok_or(Error::Truncated("Huffman tree description"))?
+                .iter()
+                .flat_map(|byte| [byte >> 4, byte & 0b1111])
+                .take(number_of_symbols)
+                .collect::<Vec<_>>();
+            Self {
+                weights,
+                len: 1 + weights_len,
+            }
+        };
+        debug!(
+            "huffman_tree_description {:02x?}",
+            &bytes[..description.len]
+        );
+        debug!("weights {:?}", description.weights);
+        Ok(description)
+    }
+
+    /// Decodes weights compressed with an FSE table, using two states
+    /// sharing the same bitstream, until the bitstream is overconsumed.
+    fn decode_fse_weights(bytes: &[u8]) -> Res<Vec<u8>> {
+        let description =
+            FseTableDescription::from_bytes(bytes, MAX_NUM_BITS, MAX_WEIGHTS_ACCURACY_LOG)?;
+        let table = FseDecodingTable::from_description(&description)?;
+        let accuracy_log = table.accuracy_log();
+
+        let mut bs = Bitstream::new(&bytes[description.len()..])?;
+
+        let state1 = bs.get_bits(accuracy_log) as u16;
+        let state2 = bs.get_bits(accuracy_log) as u16;
+        let mut decoder1 = FseDecoder::new(table.clone(), state1);
+        let mut decoder2 = FseDecoder::new(table, state2);
+
+        let mut weights = vec![];
+        loop {
+            weights.push(decoder1.symbol());
+            decoder1.update_state(&mut bs);
+            if bs.bits_remaining() < 0 {
+                weights.push(decoder2.symbol());
+                break;
+            }
+
+            weights.push(decoder2.symbol());
+            decoder2.update_state(&mut bs);
+            if bs.bits_remaining() < 0 {
+                weights.push(decoder1.symbol());
+                break;
+            }
+
+            if weights.len() > 255 {
+                return Err(Error::CorruptBitstream(
+                    "too many FSE-compressed Huffman weights",
+                ));
+            }
+        }
+
+        Ok(weights)
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Writes the weights of every symbol but the last one, compressed with
+    /// FSE or directly, whichever is shorter. None if they fit neither way.
+    pub fn encode(weights: &[u8]) -> Option<Vec<u8>> {
+        let direct = (weights.len() <= 128).then(|| {
+            let mut bytes = vec![127 + weights.len() as u8];
+            bytes.extend(weights.chunks(2).map(|pair| {
+                let low = pair.get(1).copied().unwrap_or(0);
+                (pair[0] << 4) | low
+            }));
+            bytes
+        });
+        let compressed = Self::encode_fse_weights(weights).map(|compressed_weights| {
+            let mut bytes = vec![compressed_weights.len() as u8];
+            bytes.extend(compressed_weights);
+            bytes
+        });
+
+        match (direct, compressed) {
+            (Some(direct), Some(compressed)) if compressed.len() < direct.len() => Some(compressed),
+            (Some(direct), _) => Some(direct),
+            (None, compressed) => compressed,
+        }
+    }
+
+    /// Compresses weights with two states sharing the same bitstream, the
+    /// mirror of `decode_fse_weights`. As the decoder only stops when the
+    /// bitstream is overconsumed, the result is checked by decoding it.
+    fn encode_fse_weights(weights: &[u8]) -> Option<Vec<u8>> {
+        let n = weights.len();
+        if n < 2 {
+            return None;
+        }
+        let mut counts = [0; MAX_NUM_BITS as usize + 1];
+        for weight in weights {
+            counts[*weight as usize] += 1;
+        }
+        let table = FseEncodingTable::from_counts(&counts, MAX_WEIGHTS_ACCURACY_LOG);
+
+        // The first state decodes the weights of even index, the second one
+        // the weights of odd index.
+        let mut encoders = [n - 2, n - 1].map(|i| FseEncoder::new(&table, weights[i]));
+        if n % 2 == 1 {
+            encoders.swap(0, 1);
+        }
+        let mut writer = BitWriter::new();
+        for i in (0..n - 2).rev() {
+            encoders[i % 2].encode(&mut writer, weights[i]);
+        }
+        let [even, odd] = encoders;
+        odd.flush(&mut writer);
+        even.flush(&mut writer);
+
+        let mut bytes = table.description();
+        bytes.extend(writer.close());
+        let decoded = Self::decode_fse_weights(&bytes).ok()?;
+        (bytes.len() < 128 && decoded == weights).then_some(bytes)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct HuffmanDecodingTableEntry {
+    symbol: u8,
+    num_bits: u8,
+}
+
+#[derive(Clone, Debug)]
+pub struct HuffmanDecodingTable {
+    entries: Vec<HuffmanDecodingTableEntry>,
+    max_num_bits: u8,
+}
+
+impl HuffmanDecodingTable {
+    pub fn from_tree_description(description: &HuffmanTreeDescription) -> Res<Self> {
+        Self::from_weights(&description.weights)
+    }
+
+    /// Builds the decoding table from the weights of every symbol but the
+    /// last one, whose weight is deduced so that the weights sum up to a
+    /// power of 2.
+    pub fn from_weights(weights: &[u8]) -> Res<Self> {
+        if weights.len() > 255 {
+            return Err(Error::CorruptBitstream("too many Huffman weights"));
+        }
+        if weights.iter().any(|w| *w > MAX_NUM_BITS) {
+            return Err(Error::CorruptBitstream("Huffman weight is too big"));
+        }
+
+        let weight_sum = weights
+            .iter()
+            .filter(|w| **w > 0)
+            .map(|w| 1u32 << (w - 1))
+            .sum::<u32>();
+        if weight_sum == 0 {
+            return Err(Error::CorruptBitstream("Huffman weights are all zero"));
+        }
+
+        let max_num_bits = (weight_sum.ilog2() + 1) as u8;
+        if max_num_bits > MAX_NUM_BITS {
+            return Err(Error::CorruptBitstream(
+                "Huffman max number of bits is too big",
+            ));
+        }
+
+        let remainder = (1 << max_num_bits) - weight_sum;
+        if !remainder.is_power_of_two() {
+            return Err(Error::CorruptBitstream(
+                "last Huffman weight is not a power of 2",
+            ));
+        }
+        let last_weight = (remainder.ilog2() + 1) as u8;
+
+        let mut weights = weights.to_vec();
+        weights.push(last_weight);
+
+        let table_size = 1 << max_num_bits;
+        let mut entries = Vec::with_capacity(table_size);
+        for weight in 1..=max_num_bits {
+            for (symbol, _) in weights.iter().enumerate().filter(|(_, w)| **w == weight) {
+                let entry = HuffmanDecodingTableEntry {
+                    symbol: symbol as u8,
+                    num_bits: max_num_bits + 1 - weight,
+                };
+                entries.extend(std::iter::repeat_n(entry, 1 << (weight - 1)));
+            }
+        }
+        debug_assert_eq!(entries.len(), table_size);
+
+        Ok(Self {
+            entries,
+            max_num_bits,
+        })
+    }
+
+    /// Decodes a single Huffman-coded stream which must regenerate exactly
+    /// `regenerated_size` bytes.
+    pub fn decode_stream(&self, bytes: &[u8], regenerated_size: usize) -> Res<Vec<u8>> {
+        let mut bs = Bitstream::new(bytes)?;
+        let mut output = Vec::with_capacity(regenerated_size);
+
+        for _ in 0..regenerated_size {
+            let index = bs.peek_bits(self.max_num_bits) as usize;
+            let entry = self.entries[index];
+            bs.consume_bits(entry.num_bits);
+            output.push(entry.symbol);
+        }
+
+        bs.check_fully_consumed()?;
+
+        Ok(output)
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+struct HuffmanCode {
+    code: u16,
+    num_bits: u8,
+}
+
+/// Encoding counterpart of `HuffmanDecodingTable`, built from the number of
+/// occurrences of each literal.
+#[derive(Debug)]
+pub struct HuffmanEncodingTable {
+    /// Weights of the symbols up to the last one present.
+    weights: Vec<u8>,
+    codes: Vec<HuffmanCode>,
+}
+
+impl HuffmanEncodingTable {
+    /// Builds a table for `counts` holding at least 2 symbols, with codes of
+    /// at most `MAX_NUM_BITS` bits.
+    pub fn from_counts(counts: &[u32]) -> Self {
+        let lengths = code_lengths(counts);
+        let max_num_bits = *lengths.iter().max().unwrap();
+        let last_symbol = lengths.iter().rposition(|len| *len > 0).unwrap();
+        let weights = lengths[..=last_symbol]
+            .iter()
+            .map(|len| if *len > 0 { max_num_bits + 1 - len } else { 0 })
+            .collect::<Vec<_>>();
+
+        // Codes are given in the order of the entries of the decoding table,
+        // from the longest to the shortest and by increasing symbol.
+        let mut codes = vec![HuffmanCode::default(); weights.len()];
+        let mut position = 0u32;
+        for weight in 1..=max_num_bits {
+            for (symbol, _) in weights.iter().enumerate().filter(|(_, w)| **w == weight) {
+                codes[symbol] = HuffmanCode {
+                    code: (position >> (weight - 1)) as u16,
+                    num_bits: max_num_bits + 1 - weight,
+                };
+                position += 1 << (weight - 1);
+            }
+        }
+        debug_assert_eq!(position, 1 << max_num_bits);
+
+        Self { weights, codes }
+    }
+
+    /// Tree description of the table, None if its weights cannot be written.
+    pub fn description(&self) -> Option<Vec<u8>> {
+        HuffmanTreeDescription::encode(&self.weights[..self.weights.len() - 1])
+    }
+
+    /// Writes `literals` as a single stream, read by `decode_stream`.
+    pub fn encode_stream(&self, literals: &[u8]) -> Vec<u8> {
+        let mut writer = BitWriter::new();
+        for literal in literals.iter().rev() {
+            let code = self.codes[*literal as usize];
+            writer.add_bits(code.code as u64, code.num_bits);
+        }
+        writer.close()
+    }
+}
+
+/// Lengths of the Huffman codes of the symbols counted in `counts`, 0 for the
+/// absent ones. The counts are flattened until no code is longer than
+/// `MAX_NUM_BITS`, all of them end up equal otherwise.
+fn code_lengths(counts: &[u32]) -> Vec<u8> {
+    let mut counts = counts.to_vec();
+    loop {
+        let lengths = tree_depths(&counts);
+        if lengths.iter().all(|len| *len <= MAX_NUM_BITS) {
+            return lengths;
+        }
+        for count in counts.iter_mut().filter(|count| **count > 0) {
+            *count = (*count / 2).max(1);
+        }
+    }
+}
+
+/// Depths of the leaves of the Huffman tree of `counts`, built by merging
+/// the two lightest nodes among the leaves sorted by count and the internal
+/// nodes, created by increasing count.
+fn tree_depths(counts: &[u32]) -> Vec<u8> {
+    let mut leaves = (0..counts.len())
+        .filter(|symbol| counts[*symbol] > 0)
+        .collect::<Vec<_>>();
+    leaves.sort_by_key(|symbol| counts[*symbol]);
+    let n = leaves.len();
+
+    let mut node_counts = leaves
+        .iter()
+        .map(|symbol| counts[*symbol] as u64)
+        .collect::<Vec<_>>();
+    let mut parents = vec![0; 2 * n - 1];
+    let mut next_leaf = 0;
+    let mut next_internal = n;
+    for node in n..2 * n - 1 {
+        let mut lightest = || {
+            if next_leaf < n
+                && (next_internal == node || node_counts[next_leaf] <= node_counts[next_internal])
+            {
+                next_leaf += 1;
+                next_leaf - 1
+            } else {
+                next_internal += 1;
+                next_internal - 1
+            }
+        };
+        let (a, b) = (lightest(), lightest());
+        parents[a] = node;
+        parents[b] = node;
+        node_counts.push(node_counts[a] + node_counts[b]);
+    }
+
+    let mut depths = vec![0u8; 2 * n - 1];
+    for node in (0..2 * n - 2).rev() {
+        depths[node] = depths[parents[node]] + 1;
+    }
+    let mut lengths = vec![0; counts.len()];
+    for (leaf, symbol) in leaves.iter().enumerate() {
+        lengths[*symbol] = depths[leaf];
+    }
+    lengths
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_rng::TestRng;
+
+    #[test]
+    fn rfc_example_table() {
+        // Literals 0 to 4 from the RFC example, literal 5 gets the deduced
+        // last weight.
+        let table = HuffmanDecodingTable::from_weights(&[4, 3, 2, 0, 1]).unwrap();
+        assert_eq!(table.max_num_bits, 4);
+
+        let expected = [
+            (4, 4),
+            (5, 4),
+            (2, 3),
+            (2, 3),
+            (1, 2),
+            (1, 2),
+            (1, 2),
+            (1, 2),
+        ]
+        .into_iter()
+        .chain(std::iter::repeat_n((0, 1), 8))
+        .map(|(symbol, num_bits)| HuffmanDecodingTableEntry { symbol, num_bits })
+        .collect::<Vec<_>>();
+        assert_eq!(table.entries, expected);
+    }
+
+    #[test]
+    fn direct_weights() {
+        let description = HuffmanTreeDescription::from_bytes(&[132, 0x43, 0x20, 0x10]).unwrap();
+        assert_eq!(description.weights, [4, 3, 2, 0, 1]);
+        assert_eq!(description.len(), 4);
+    }
+
+    #[test]
+    fn decode_stream() {
+        let table = HuffmanDecodingTable::from_weights(&[4, 3, 2, 0, 1]).unwrap();
+        // Codes read from the end: 1 (0), 01 (1), 001 (2), 0001 (5), 0000 (4)
+        // preceded by the padding marker.
+        let bits = "1".to_owned() + "1" + "01" + "001" + "0001" + "0000";
+        let padded = format!("{:0>16}", bits);
+        let value = u16::from_str_radix(&padded, 2).unwrap();
+        let bytes = value.to_le_bytes();
+
+        let actual = table.decode_stream(&bytes, 5).unwrap();
+        assert_eq!(actual, [0, 1, 2, 5, 4]);
+    }
+
+    /// Encodes `literals` with a table built from their counts and decodes
+    /// them with the table read back from its description.
+    fn round_trip(literals: &[u8]) -> HuffmanTreeDescription {
+        let mut counts = [0; 256];
+        for literal in literals {
+            counts[*literal as usize] += 1;
+        }
+        let table = HuffmanEncodingTable::from_counts(&counts);
+        let bytes = table.description().unwrap();
+        let description = HuffmanTreeDescription::from_bytes(&bytes).unwrap();
+        assert_eq!(description.len(), bytes.len());
+
+        let decoding_table = HuffmanDecodingTable::from_tree_description(&description).unwrap();
+        let stream = table.encode_stream(literals);
+        let actual = decoding_table
+            .decode_stream(&stream, literals.len())
+            .unwrap();
+        assert_eq!(actual, literals);
+        description
+    }
+
+    #[test]
+    fn code_lengths_are_limited() {
+        // Fibonacci counts give a tree as deep as the number of symbols.
+        let mut counts = vec![1u32, 1];
+        while counts.len() < 30 {
+            counts.push(counts[counts.len() - 1] + counts[counts.len() - 2]);
+        }
+        assert_eq!(*tree_depths(&counts).iter().max().unwrap(), 29);
+
+        let lengths = code_lengths(&counts);
+        assert_eq!(*lengths.iter().max().unwrap(), MAX_NUM_BITS);
+        let kraft_sum = lengths
+            .iter()
+            .map(|len| 1u32 << (MAX_NUM_BITS - len))
+            .sum::<u32>();
+        assert_eq!(kraft_sum, 1 << MAX_NUM_BITS);
+    }
+
+    #[test]
+    fn encode_streams() {
+        let text = b"the quick brown fox jumps over the lazy dog, again and again";
+        // Weights are compressed when it is shorter than writing them directly.
+        let description = round_trip(text);
+        assert!(description.len() < 1 + description.weights.len().div_ceil(2));
+        let description = round_trip(&[7, 7, 7, 7, 7, 3]);
+        assert_eq!(description.len(), 1 + description.weights.len().div_ceil(2));
+
+        // Weights up to symbol 255 cannot be written directly.
+        let mut rng = TestRng::new();
+        let literals = (0..5000)
+            .map(|i| rng.geometric(8) * 31 + (i % 3) as u8)
+            .collect::<Vec<_>>();
+        let description = round_trip(&literals);
+        assert!(description.weights.len() > 128);
+    }
+}
@@ -3,39 +3,135 @@
 mod bitstream;
 mod block;
 mod compressed_block;
+mod decoder;
+mod dictionary;
+mod encoder;
+mod error;
 mod frame;
 mod fse;
+mod huffman;
+mod match_finder;
+mod push_decoder;
+#[cfg(test)]
+mod test_rng;
+mod xxhash;
 
 use crate::frame::Frame;
 
-pub type Res<T> = Result<T, Box<dyn std::error::Error>>;
+pub use crate::decoder::ZstdDecoder;
+pub use crate::dictionary::Dictionary;
+pub use crate::encoder::{DEFAULT_LEVEL, MAX_LEVEL};
+pub use crate::error::Error;
+pub use crate::frame::SkippableFrame;
+pub use crate::push_decoder::PushDecoder;
+
+pub type Res<T> = Result<T, Error>;
+
+#[cfg(feature = "fuzzing")]
+#[doc(hidden)]
+pub mod fuzzing {
+    pub use crate::bitstream::{Bitstream, MAX_READ_BITS};
+    pub use crate::fse::{FseDecoder, FseDecodingTable};
+}
 
 pub struct Zstd {
     frames: Vec<Frame>,
+    skippable_frames: Vec<SkippableFrame>,
+    verify_checksums: bool,
+    dictionaries: Vec<Dictionary>,
+    prefix: Option<Dictionary>,
 }
 
 impl Zstd {
     pub fn from_bytes(bytes: Vec<u8>) -> Res<Self> {
         let mut frames = vec![];
+        let mut skippable_frames = vec![];
         let mut bytes: &[u8] = &bytes;
 
         while !bytes.is_empty() {
+            if SkippableFrame::is_skippable(bytes) {
+                let mut frame = SkippableFrame::from_bytes(bytes)?;
+                frame.index = frames.len() + skippable_frames.len();
+                bytes = &bytes[frame.len()..];
+                skippable_frames.push(frame);
+                continue;
+            }
+
             let frame = Frame::from_bytes(bytes)?;
             bytes = &bytes[frame.len()..];
             frames.push(frame);
         }
 
-        Ok(Self { frames })
+        Ok(Self {
+            frames,
+            skippable_frames,
+            verify_checksums: true,
+            dictionaries: vec![],
+            prefix: None,
+        })
+    }
+
+    /// Skippable frames found in the input, in order, each recording its
+    /// position among all the frames. They are ignored when decoding.
+    pub fn skippable_frames(&self) -> &[SkippableFrame] {
+        &self.skippable_frames
+    }
+
+    /// Enables or disables the verification of the content checksum of
+    /// frames, enabled by default. Skipping it makes decoding faster.
+    pub fn verify_checksums(mut self, verify_checksums: bool) -> Self {
+        self.verify_checksums = verify_checksums;
+        self
+    }
+
+    /// Makes a dictionary available to decode the frames referencing its ID.
+    /// Frames without a dictionary ID are decoded with the first dictionary.
+    pub fn with_dictionary(mut self, dictionary: Dictionary) -> Self {
+        self.dictionaries.push(dictionary);
+        self
     }
 
-    pub fn encode(_bytes: Vec<u8>) -> Vec<u8> {
-        todo!();
+    /// References the content a frame was compressed against, so that its
+    /// matches can reach back into it. The prefix is used for every frame,
+    /// instead of any dictionary.
+    pub fn with_prefix(mut self, prefix: Vec<u8>) -> Self {
+        self.prefix = Some(Dictionary::from_raw_content(prefix));
+        self
     }
 
-    pub fn decode(self) -> Vec<u8> {
-        self.frames
-            .into_iter()
-            .flat_map(|frame| frame.decode())
-            .collect()
+    fn find_dictionary(&self, dictionary_id: u32) -> Res<Option<&Dictionary>> {
+        dictionary::find_dictionary(&self.dictionaries, self.prefix.as_ref(), dictionary_id)
     }
+
+    /// Compresses `bytes` into a single frame with a content checksum, at
+    /// the default level.
+    pub fn encode(bytes: Vec<u8>) -> Res<Vec<u8>> {
+        Self::encode_with_level(bytes, DEFAULT_LEVEL)
+    }
+
+    /// Compresses `bytes` into a single frame with a content checksum, at a
+    /// level from 1, the fastest, to `MAX_LEVEL`, the strongest. Level 0 is
+    /// the default level and the levels above `MAX_LEVEL` are `MAX_LEVEL`.
+    pub fn encode_with_level(bytes: Vec<u8>, level: u8) -> Res<Vec<u8>> {
+        let parameters = encoder::level_parameters(level, bytes.len());
+        Ok(encoder::encode_frame(&bytes, parameters))
+    }
+
+    pub fn decode(mut self) -> Res<Vec<u8>> {
+        let mut output = vec![];
+        for frame in std::mem::take(&mut self.frames) {
+            let dictionary = self.find_dictionary(frame.dictionary_id())?;
+            output.extend(frame.decode(self.verify_checksums, dictionary)?);
+        }
+        Ok(output)
+    }
+}
+
+/// Reads a fixed-size field at the start of `bytes`, such as a little-endian
+/// integer, failing if `bytes` is too short.
+pub(crate) fn read_array<const N: usize>(bytes: &[u8], structure: &'static str) -> Res<[u8; N]> {
+    bytes
+        .get(..N)
+        .and_then(|bytes| bytes.try_into().ok())
+        .ok_or(Error::Truncated(structure))
 }
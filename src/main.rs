@@ -1,7 +1,12 @@
 use clap::{Args, Parser};
-use std::{ffi::OsStr, fs, path::PathBuf};
+use std::{
+    ffi::OsStr,
+    fs::{self, File},
+    io::{self, BufReader, BufWriter, Write},
+    path::PathBuf,
+};
 
-use zstd::{Res, Zstd};
+use zstd::{Zstd, ZstdDecoder, DEFAULT_LEVEL, MAX_LEVEL};
 
 #[derive(Parser)]
 #[command(version, about, long_about = None)]
@@ -9,6 +14,15 @@ struct Cli {
     #[command(flatten)]
     encode_or_decode: EncodeOrDecode,
 
+    /// Skip the verification of the content checksum when decoding
+    #[arg(long)]
+    no_check: bool,
+
+    /// Compression level, from 1 (fastest) to 19 (strongest)
+    #[arg(short, long, default_value_t = DEFAULT_LEVEL,
+          value_parser = clap::value_parser!(u8).range(1..=MAX_LEVEL as i64))]
+    level: u8,
+
     input_path: PathBuf,
 }
 
@@ -22,15 +36,15 @@ struct EncodeOrDecode {
     decode: bool,
 }
 
-fn main() -> Res<()> {
+fn main() -> Result<(), Box<dyn std::error::Error>> {
     env_logger::init();
 
     let mut cli = Cli::parse();
-    let input_bytes = fs::read(&cli.input_path)?;
 
     if cli.encode_or_decode.encode {
+        let input_bytes = fs::read(&cli.input_path)?;
         let output_path = cli.input_path.to_str().unwrap().to_string() + ".zst";
-        let output_bytes = Zstd::encode(input_bytes);
+        let output_bytes = Zstd::encode_with_level(input_bytes, cli.level)?;
 
         if fs::exists(&output_path)? {
             println!("Overwriting output file...");
@@ -40,15 +54,18 @@ fn main() -> Res<()> {
         if cli.input_path.extension() != Some(OsStr::new("zst")) {
             return Err("File name to decode should end with .zst".into());
         }
+        let input = BufReader::new(File::open(&cli.input_path)?);
+        let mut decoder = ZstdDecoder::new(input).verify_checksums(!cli.no_check);
+
         cli.input_path.set_extension("");
         let output_path = cli.input_path;
 
-        let output_bytes = Zstd::from_bytes(input_bytes)?.decode();
-
         if fs::exists(&output_path)? {
             println!("Overwriting output file...");
         }
-        fs::write(output_path, output_bytes)?;
+        let mut output = BufWriter::new(File::create(output_path)?);
+        io::copy(&mut decoder, &mut output)?;
+        output.flush()?;
     }
 
     Ok(())
@@ -0,0 +1,455 @@
+use crate::compressed_block::Sequence;
+
+/// The search skips more and more positions as the literals since the last
+/// match grow, one more every `1 << SEARCH_STRENGTH` literals.
+const SEARCH_STRENGTH: u32 = 8;
+/// Number of bytes hashed by the table of long matches of double fast.
+const LONG_MATCH: u8 = 8;
+
+/// How matches are looked for, from the fastest to the strongest.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Strategy {
+    /// A single candidate per position, the last position with its hash.
+    Fast,
+    /// The candidates of a table of long matches and of a table of short
+    /// ones.
+    DoubleFast,
+    /// The best match among the positions chained by hash.
+    Greedy,
+    /// Like greedy, then a better match at the next position is preferred.
+    Lazy,
+    /// Like lazy, looking up to two positions ahead.
+    Lazy2,
+}
+
+/// Parameters of the match finder, as chosen by a compression level.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MatchFinderParameters {
+    /// Matches reach at most `1 << window_log` bytes back.
+    pub window_log: u8,
+    /// Size of the hash chain, or of the table of short matches of double
+    /// fast.
+    pub chain_log: u8,
+    pub hash_log: u8,
+    /// At most `1 << search_log` positions of a hash chain are compared.
+    pub search_log: u8,
+    /// Shortest match looked for, also the number of bytes hashed.
+    pub min_match: u8,
+    /// A match at least this long ends the search in a hash chain, unused
+    /// by the fast strategies.
+    pub target_length: u32,
+    pub strategy: Strategy,
+}
+
+/// Sequences of a block and the literals they copy.
+#[derive(Debug, Default)]
+pub struct BlockSequences {
+    pub sequences: Vec<Sequence>,
+    /// Literals of all the sequences, followed by the last literals of the
+    /// block.
+    pub literals: Vec<u8>,
+}
+
+/// Match of `length` bytes from `start`, copied from `distance` bytes back.
+#[derive(Clone, Copy, Debug)]
+struct Match {
+    start: usize,
+    length: usize,
+    distance: usize,
+}
+
+impl Match {
+    /// Estimated value of the match: its length, minus the cost of its
+    /// offset.
+    fn gain(&self) -> i64 {
+        4 * self.length as i64 - self.distance.ilog2() as i64
+    }
+}
+
+/// Match finder looking for matches at the repeated offsets and at the
+/// positions seen so far with the same hash, as many as the strategy allows.
+pub struct MatchFinder {
+    parameters: MatchFinderParameters,
+    /// Last position of the frame content whose first bytes have each hash,
+    /// with `LONG_MATCH` bytes hashed for double fast.
+    hash_table: Vec<u32>,
+    /// Previous position with the same hash for the hash chain strategies,
+    /// indexed by position modulo its size. Last position of each short hash
+    /// for double fast, unused by fast.
+    chain_table: Vec<u32>,
+    /// First position not inserted in the hash chain yet.
+    next_to_insert: usize,
+    window_size: usize,
+}
+
+impl MatchFinder {
+    pub fn new(parameters: MatchFinderParameters) -> Self {
+        let chain_size = match parameters.strategy {
+            Strategy::Fast => 0,
+            _ => 1 << parameters.chain_log,
+        };
+        Self {
+            parameters,
+            hash_table: vec![0; 1 << parameters.hash_log],
+            chain_table: vec![0; chain_size],
+            next_to_insert: 0,
+            window_size: 1 << parameters.window_log,
+        }
+    }
+
+    /// Finds the sequences of `input[start..end]`, whose matches may reach
+    /// back into the previous blocks of `input`. The repeated offsets are
+    /// updated as the decoder does for the sequences found.
+    pub fn find_sequences(
+        &mut self,
+        input: &[u8],
+        start: usize,
+        end: usize,
+        repeated_offsets: &mut [u32; 3],
+    ) -> BlockSequences {
+        let mut block = BlockSequences::default();
+        let mut anchor = start;
+        let mut position = start;
+
+        while position + self.parameters.min_match as usize <= end {
+            let found = match self.parameters.strategy {
+                Strategy::Fast => self.find_fast(input, anchor, position, end, repeated_offsets),
+                Strategy::DoubleFast => {
+                    self.find_double_fast(input, anchor, position, end, repeated_offsets)
+                }
+                Strategy::Greedy => {
+                    self.find_lazy(input, anchor, position, end, repeated_offsets, 0)
+                }
+                Strategy::Lazy => self.find_lazy(input, anchor, position, end, repeated_offsets, 1),
+                Strategy::Lazy2 => {
+                    self.find_lazy(input, anchor, position, end, repeated_offsets, 2)
+                }
+            };
+            let Some(found) = found else {
+                position += 1 + ((position - anchor) >> SEARCH_STRENGTH);
+                continue;
+            };
+
+            let match_end = found.start + found.length;
+            let literals_length = (found.start - anchor) as u32;
+            block
+                .literals
+                .extend_from_slice(&input[anchor..found.start]);
+            block.sequences.push(Sequence {
+                ll: literals_length,
+                ml: found.length as u32,
+                of: offset_value(repeated_offsets, found.distance as u32, literals_length),
+            });
+
+            // Positions inside the match are only partially indexed by the
+            // hash tables, the hash chain catches up on the next search.
+            for indexed in [found.start + 2, match_end - 2] {
+                if indexed + self.parameters.min_match as usize <= end {
+                    self.insert(input, indexed);
+                }
+            }
+            anchor = match_end;
+            position = match_end;
+        }
+
+        block.literals.extend_from_slice(&input[anchor..end]);
+        block
+    }
+
+    /// Looks for a match at the first repeated offset, then at the last
+    /// position with the same hash.
+    fn find_fast(
+        &mut self,
+        input: &[u8],
+        anchor: usize,
+        position: usize,
+        end: usize,
+        repeated_offsets: &[u32; 3],
+    ) -> Option<Match> {
+        let min_match = self.parameters.min_match;
+        let hash = hash(input, position, min_match, self.parameters.hash_log);
+        let candidate = std::mem::replace(&mut self.hash_table[hash], position as u32);
+
+        self.repeated_match(input, anchor, position, end, repeated_offsets)
+            .or_else(|| self.match_at(input, anchor, position, end, position - candidate as usize))
+    }
+
+    /// Looks for a match at the first repeated offset, then takes the longest
+    /// of the matches at the last positions with the same long and short
+    /// hashes.
+    fn find_double_fast(
+        &mut self,
+        input: &[u8],
+        anchor: usize,
+        position: usize,
+        end: usize,
+        repeated_offsets: &[u32; 3],
+    ) -> Option<Match> {
+        let [long_candidate, short_candidate] = self.insert_double_fast(input, position);
+
+        if let Some(found) = self.repeated_match(input, anchor, position, end, repeated_offsets) {
+            return Some(found);
+        }
+        [long_candidate, short_candidate]
+            .into_iter()
+            .filter_map(|candidate| {
+                self.match_at(input, anchor, position, end, position - candidate)
+            })
+            .max_by_key(|found| found.length)
+    }
+
+    /// Looks for the best match at `position`, then at the next `depth`
+    /// positions for a match worth emitting more literals first.
+    fn find_lazy(
+        &mut self,
+        input: &[u8],
+        anchor: usize,
+        mut position: usize,
+        end: usize,
+        repeated_offsets: &[u32; 3],
+        depth: usize,
+    ) -> Option<Match> {
+        let mut best = self.best_match(input, anchor, position, end, repeated_offsets)?;
+
+        'improve: loop {
+            for step in 1..=depth {
+                let next = position + step;
+                if next + self.parameters.min_match as usize > end {
+                    break 'improve;
+                }
+                // Each literal skipped must be paid for by a better match.
+                let threshold = best.gain() + 3 * step as i64 + 1;
+                if let Some(found) = self.best_match(input, anchor, next, end, repeated_offsets)
+                    && found.gain() > threshold
+                {
+                    best = found;
+                    position = next;
+                    continue 'improve;
+                }
+            }
+            break;
+        }
+        Some(best)
+    }
+
+    /// Best of the match at the first repeated offset and of the matches of
+    /// the hash chain of `position`.
+    fn best_match(
+        &mut self,
+        input: &[u8],
+        anchor: usize,
+        position: usize,
+        end: usize,
+        repeated_offsets: &[u32; 3],
+    ) -> Option<Match> {
+        let repeated = self.repeated_match(input, anchor, position, end, repeated_offsets);
+        let found = self.search_chain(input, anchor, position, end);
+        match (repeated, found) {
+            (Some(repeated), Some(found)) if found.length > repeated.length => Some(found),
+            (Some(repeated), _) => Some(repeated),
+            (None, found) => found,
+        }
+    }
+
+    /// Compares the positions chained with the hash of `position`, from the
+    /// closest, until the search limit or a match of the target length.
+    fn search_chain(
+        &mut self,
+        input: &[u8],
+        anchor: usize,
+        position: usize,
+        end: usize,
+    ) -> Option<Match> {
+        self.insert_up_to(input, position);
+        let chain_size = self.chain_table.len();
+        let max_distance = position.min(self.window_size);
+
+        let hash = hash(
+            input,
+            position,
+            self.parameters.min_match,
+            self.parameters.hash_log,
+        );
+        let mut candidate = self.hash_table[hash] as usize;
+        let mut best: Option<(usize, usize)> = None;
+        for _ in 0..1 << self.parameters.search_log {
+            let distance = position - candidate;
+            if distance == 0 || distance > max_distance {
+                break;
+            }
+            let length = common_length(&input[position..end], &input[candidate..]);
+            if best.is_none_or(|(best_length, _)| length > best_length) {
+                best = Some((length, distance));
+                if length >= self.parameters.target_length as usize {
+                    break;
+                }
+            }
+
+            // The link of a position a whole chain back was overwritten.
+            let previous = self.chain_table[candidate % chain_size] as usize;
+            if previous >= candidate || distance >= chain_size {
+                break;
+            }
+            candidate = previous;
+        }
+
+        let (_, distance) = best?;
+        self.match_at(input, anchor, position, end, distance)
+    }
+
+    /// Match at the first repeated offset, only looked for after literals as
+    /// the offset value 1 means the second repeated offset without them.
+    fn repeated_match(
+        &self,
+        input: &[u8],
+        anchor: usize,
+        position: usize,
+        end: usize,
+        repeated_offsets: &[u32; 3],
+    ) -> Option<Match> {
+        if position == anchor {
+            return None;
+        }
+        self.match_at(input, anchor, position, end, repeated_offsets[0] as usize)
+    }
+
+    /// Match at `position` copied from `distance` bytes back, extended
+    /// backward over the literals. None if it is too short or out of the
+    /// window.
+    fn match_at(
+        &self,
+        input: &[u8],
+        anchor: usize,
+        position: usize,
+        end: usize,
+        distance: usize,
+    ) -> Option<Match> {
+        if distance == 0 || distance > position.min(self.window_size) {
+            return None;
+        }
+        let length = common_length(&input[position..end], &input[position - distance..]);
+        if length < self.parameters.min_match as usize {
+            return None;
+        }
+
+        let mut start = position;
+        while start > anchor && start > distance && input[start - 1] == input[start - 1 - distance]
+        {
+            start -= 1;
+        }
+        Some(Match {
+            start,
+            length: length + position - start,
+            distance,
+        })
+    }
+
+    /// Records `position` in the hash tables of the fast strategies.
+    fn insert(&mut self, input: &[u8], position: usize) {
+        match self.parameters.strategy {
+            Strategy::Fast => {
+                let hash = hash(
+                    input,
+                    position,
+                    self.parameters.min_match,
+                    self.parameters.hash_log,
+                );
+                self.hash_table[hash] = position as u32;
+            }
+            Strategy::DoubleFast => {
+                self.insert_double_fast(input, position);
+            }
+            Strategy::Greedy | Strategy::Lazy | Strategy::Lazy2 => {}
+        }
+    }
+
+    /// Records `position` in the tables of long and short matches, returning
+    /// the previous positions with the same hashes.
+    fn insert_double_fast(&mut self, input: &[u8], position: usize) -> [usize; 2] {
+        let long_hash = hash(input, position, LONG_MATCH, self.parameters.hash_log);
+        let short_hash = hash(
+            input,
+            position,
+            self.parameters.min_match,
+            self.parameters.chain_log,
+        );
+        [
+            std::mem::replace(&mut self.hash_table[long_hash], position as u32) as usize,
+            std::mem::replace(&mut self.chain_table[short_hash], position as u32) as usize,
+        ]
+    }
+
+    /// Chains the positions before `target` not inserted yet.
+    fn insert_up_to(&mut self, input: &[u8], target: usize) {
+        let chain_size = self.chain_table.len();
+        for position in self.next_to_insert..target {
+            let hash = hash(
+                input,
+                position,
+                self.parameters.min_match,
+                self.parameters.hash_log,
+            );
+            self.chain_table[position % chain_size] =
+                std::mem::replace(&mut self.hash_table[hash], position as u32);
+        }
+        self.next_to_insert = self.next_to_insert.max(target);
+    }
+}
+
+/// Hash of the first `len` bytes at `position`, the bytes past the end of
+/// `input` being zeroes.
+fn hash(input: &[u8], position: usize, len: u8, hash_log: u8) -> usize {
+    let bytes = match input.get(position..position + 8) {
+        Some(bytes) => u64::from_le_bytes(bytes.try_into().unwrap()),
+        None => {
+            let mut bytes = [0; 8];
+            bytes[..input.len() - position].copy_from_slice(&input[position..]);
+            u64::from_le_bytes(bytes)
+        }
+    };
+    let bytes = bytes << (64 - 8 * len as u32);
+    (bytes.wrapping_mul(0x9E37_79B1_85EB_CA87) >> (64 - hash_log)) as usize
+}
+
+/// Number of bytes `a` and `b` have in common from their start.
+fn common_length(a: &[u8], b: &[u8]) -> usize {
+    let mut len = 0;
+    for (a, b) in a.chunks_exact(8).zip(b.chunks_exact(8)) {
+        let a = u64::from_le_bytes(a.try_into().unwrap());
+        let b = u64::from_le_bytes(b.try_into().unwrap());
+        let difference = a ^ b;
+        if difference != 0 {
+            return len + (difference.trailing_zeros() / 8) as usize;
+        }
+        len += 8;
+    }
+    len + a[len..]
+        .iter()
+        .zip(&b[len..])
+        .take_while(|(a, b)| a == b)
+        .count()
+}
+
+/// Offset value of a match `distance` bytes back, using a repeat code when
+/// the distance is one of the repeated offsets. The repeated offsets are
+/// updated the same way as when decoding the value.
+fn offset_value(repeated_offsets: &mut [u32; 3], distance: u32, literals_length: u32) -> u32 {
+    let [first, second, third] = *repeated_offsets;
+    let (offset_value, updated) = if literals_length > 0 && distance == first {
+        (1, [first, second, third])
+    } else if literals_length > 0 && distance == second {
+        (2, [second, first, third])
+    } else if literals_length > 0 && distance == third {
+        (3, [third, first, second])
+    } else if literals_length == 0 && distance == second {
+        (1, [second, first, third])
+    } else if literals_length == 0 && distance == third {
+        (2, [third, first, second])
+    } else if literals_length == 0 && distance + 1 == first {
+        (3, [distance, first, second])
+    } else {
+        (distance + 3, [distance, first, second])
+    };
+    *repeated_offsets = updated;
+    offset_value
+}
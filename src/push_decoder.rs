@@ -0,0 +1,241 @@
+use log::debug;
+
+use crate::block::{Block, BlockHeader};
+use crate::dictionary::{self, Dictionary};
+use crate::frame::{DecodingContext, FrameHeader, SkippableFrame, MAGIC_NUMBER};
+use crate::xxhash::Xxh64;
+use crate::{Error, Res};
+
+/// Incremental decoder which doesn't perform any IO: compressed input is
+/// pushed in chunks of any size with `feed`, and the output of every block
+/// decoded so far is collected with `take_output`. Headers, blocks and
+/// checksums split across chunks are buffered until they are complete.
+///
+/// After an error the decoder is left in an unspecified state and should
+/// not be fed anymore.
+pub struct PushDecoder {
+    verify_checksums: bool,
+    dictionaries: Vec<Dictionary>,
+    prefix: Option<Dictionary>,
+    state: State,
+    input: Vec<u8>,
+    output: Vec<u8>,
+}
+
+enum State {
+    /// Expecting the magic number of the next frame.
+    Magic,
+    /// Inside a skippable frame, with the number of bytes left to skip.
+    Skipping(u64),
+    FrameHeader,
+    Block(FrameState),
+    Checksum(FrameState),
+}
+
+/// Decoding state of the frame being read.
+struct FrameState {
+    header: FrameHeader,
+    context: DecodingContext,
+    hasher: Xxh64,
+}
+
+impl Default for PushDecoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PushDecoder {
+    pub fn new() -> Self {
+        Self {
+            verify_checksums: true,
+            dictionaries: vec![],
+            prefix: None,
+            state: State::Magic,
+            input: vec![],
+            output: vec![],
+        }
+    }
+
+    /// Enables or disables the verification of the content checksum of
+    /// frames, enabled by default.
+    pub fn verify_checksums(mut self, verify_checksums: bool) -> Self {
+        self.verify_checksums = verify_checksums;
+        self
+    }
+
+    /// Makes a dictionary available to decode the frames referencing its ID.
+    /// Frames without a dictionary ID are decoded with the first dictionary.
+    pub fn with_dictionary(mut self, dictionary: Dictionary) -> Self {
+        self.dictionaries.push(dictionary);
+        self
+    }
+
+    /// References the content frames were compressed against, used instead
+    /// of any dictionary.
+    pub fn with_prefix(mut self, prefix: Vec<u8>) -> Self {
+        self.prefix = Some(Dictionary::from_raw_content(prefix));
+        self
+    }
+
+    /// Decodes as much of `input` as possible, buffering the rest until the
+    /// next call.
+    pub fn feed(&mut self, input: &[u8]) -> Res<()> {
+        let mut buffered = std::mem::take(&mut self.input);
+        buffered.extend_from_slice(input);
+
+        let mut position = 0;
+        let result = loop {
+            match self.step(&buffered[position..]) {
+                Ok(Some(consumed)) => position += consumed,
+                Ok(None) => break Ok(()),
+                Err(e) => break Err(e),
+            }
+        };
+
+        buffered.drain(..position);
+        self.input = buffered;
+        result
+    }
+
+    /// Output decoded since the previous call.
+    pub fn take_output(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.output)
+    }
+
+    /// Minimum number of bytes to feed before the decoder can make progress,
+    /// reading exactly that much never goes past the end of the last frame.
+    pub fn bytes_needed(&self) -> usize {
+        let expected = match &self.state {
+            State::Magic => 4,
+            State::Skipping(size) => return usize::try_from(*size).unwrap_or(usize::MAX).max(1),
+            State::FrameHeader => match self.input.first() {
+                Some(descriptor) => FrameHeader::len_from_descriptor(*descriptor),
+                None => 1,
+            },
+            State::Block(_) => match self.input.first_chunk() {
+                Some(header) => 3 + BlockHeader::from_bytes(*header).content_len().unwrap_or(0),
+                None => 3,
+            },
+            State::Checksum(_) => 4,
+        };
+        expected.saturating_sub(self.input.len()).max(1)
+    }
+
+    /// Checks that the input ended on a frame boundary, without any partial
+    /// frame left.
+    pub fn finish(&self) -> Res<()> {
+        match self.state {
+            State::Magic if self.input.is_empty() => Ok(()),
+            State::Magic => Err(Error::Truncated("magic number")),
+            State::Skipping(_) => Err(Error::Truncated("skippable frame")),
+            State::FrameHeader => Err(Error::Truncated("frame header")),
+            State::Block(_) => Err(Error::Truncated("block")),
+            State::Checksum(_) => Err(Error::Truncated("content checksum")),
+        }
+    }
+
+    /// Parses the next structure from `input` if it is complete, returning
+    /// the number of bytes consumed, or None if more input is needed.
+    fn step(&mut self, input: &[u8]) -> Res<Option<usize>> {
+        let consumed = match &mut self.state {
+            State::Magic => {
+                if SkippableFrame::is_skippable(input) {
+                    let Some(frame_size) = input.get(4..8) else {
+                        return Ok(None);
+                    };
+                    let frame_size = u32::from_le_bytes(frame_size.try_into().unwrap());
+                    debug!("skipping skippable frame of {} bytes", frame_size);
+                    self.state = State::Skipping(frame_size as u64);
+                    8
+                } else {
+                    let Some(magic_number) = input.first_chunk() else {
+                        return Ok(None);
+                    };
+                    let magic_number = u32::from_le_bytes(*magic_number);
+                    if magic_number != MAGIC_NUMBER {
+                        return Err(Error::InvalidMagic(magic_number));
+                    }
+                    self.state = State::FrameHeader;
+                    4
+                }
+            }
+            State::Skipping(size) => {
+                if input.is_empty() && *size > 0 {
+                    return Ok(None);
+                }
+                let skipped = (input.len() as u64).min(*size);
+                *size -= skipped;
+                if *size == 0 {
+                    self.state = State::Magic;
+                }
+                skipped as usize
+            }
+            State::FrameHeader => {
+                let Some(descriptor) = input.first() else {
+                    return Ok(None);
+                };
+                let Some(bytes) = input.get(..FrameHeader::len_from_descriptor(*descriptor)) else {
+                    return Ok(None);
+                };
+                let header = FrameHeader::from_bytes(bytes)?;
+
+                let mut context = DecodingContext::new(header.window_size);
+                let prefix = self.prefix.as_ref();
+                if let Some(dictionary) =
+                    dictionary::find_dictionary(&self.dictionaries, prefix, header.dictionary_id)?
+                {
+                    context.load_dictionary(dictionary);
+                }
+
+                let len = header.len;
+                self.state = State::Block(FrameState {
+                    header,
+                    context,
+                    hasher: Xxh64::new(0),
+                });
+                len
+            }
+            State::Block(frame) => {
+                let Some(header) = input.first_chunk() else {
+                    return Ok(None);
+                };
+                let block_header = BlockHeader::from_bytes(*header);
+                let Some(bytes) = input.get(..3 + block_header.content_len()?) else {
+                    return Ok(None);
+                };
+
+                let output = Block::from_bytes(bytes)?.decode(&mut frame.context)?;
+                frame.hasher.update(&output);
+                self.output.extend(output);
+
+                if block_header.is_last_block() {
+                    self.finish_frame();
+                }
+                bytes.len()
+            }
+            State::Checksum(frame) => {
+                let Some(checksum) = input.first_chunk() else {
+                    return Ok(None);
+                };
+                let expected = u32::from_le_bytes(*checksum);
+                let actual = frame.hasher.digest() as u32;
+                if self.verify_checksums && actual != expected {
+                    return Err(Error::ChecksumMismatch { expected, actual });
+                }
+                self.state = State::Magic;
+                4
+            }
+        };
+        Ok(Some(consumed))
+    }
+
+    fn finish_frame(&mut self) {
+        let State::Block(frame) = std::mem::replace(&mut self.state, State::Magic) else {
+            unreachable!();
+        };
+        if frame.header.has_content_checksum() {
+            self.state = State::Checksum(frame);
+        }
+    }
+}
@@ -0,0 +1,27 @@
+//! Reproducible pseudo-random data for the unit tests.
+
+/// Xorshift generator, always starting from the same seed.
+pub struct TestRng(u64);
+
+impl TestRng {
+    pub fn new() -> Self {
+        Self(0x2545_f491_4f6c_dd1d)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    pub fn bytes(&mut self, len: usize) -> Vec<u8> {
+        (0..len).map(|_| self.next_u64() as u8).collect()
+    }
+
+    /// Value from 0 to `max`, each one half as likely as the previous one,
+    /// like symbols of a skewed distribution.
+    pub fn geometric(&mut self, max: u8) -> u8 {
+        (self.next_u64().trailing_zeros() as u8).min(max)
+    }
+}
@@ -0,0 +1,173 @@
+// https://github.com/Cyan4973/xxHash/blob/dev/doc/xxhash_spec.md#xxh64-algorithm-description
+
+const PRIME64_1: u64 = 0x9E3779B185EBCA87;
+const PRIME64_2: u64 = 0xC2B2AE3D27D4EB4F;
+const PRIME64_3: u64 = 0x165667B19E3779F9;
+const PRIME64_4: u64 = 0x85EBCA77C2B2AE63;
+const PRIME64_5: u64 = 0x27D4EB2F165667C5;
+
+const STRIPE_LEN: usize = 32;
+
+/// Streaming XXH64 hasher, used for the content checksum of frames.
+#[derive(Debug, Clone)]
+pub struct Xxh64 {
+    seed: u64,
+    accumulators: [u64; 4],
+    buffer: [u8; STRIPE_LEN],
+    buffer_len: usize,
+    total_len: u64,
+}
+
+impl Xxh64 {
+    pub fn new(seed: u64) -> Self {
+        Self {
+            seed,
+            accumulators: [
+                seed.wrapping_add(PRIME64_1).wrapping_add(PRIME64_2),
+                seed.wrapping_add(PRIME64_2),
+                seed,
+                seed.wrapping_sub(PRIME64_1),
+            ],
+            buffer: [0; STRIPE_LEN],
+            buffer_len: 0,
+            total_len: 0,
+        }
+    }
+
+    pub fn update(&mut self, mut bytes: &[u8]) {
+        self.total_len += bytes.len() as u64;
+
+        if self.buffer_len > 0 {
+            let n = bytes.len().min(STRIPE_LEN - self.buffer_len);
+            self.buffer[self.buffer_len..self.buffer_len + n].copy_from_slice(&bytes[..n]);
+            self.buffer_len += n;
+            bytes = &bytes[n..];
+
+            if self.buffer_len < STRIPE_LEN {
+                return;
+            }
+            let stripe = self.buffer;
+            self.process_stripe(&stripe);
+            self.buffer_len = 0;
+        }
+
+        let mut stripes = bytes.chunks_exact(STRIPE_LEN);
+        for stripe in &mut stripes {
+            self.process_stripe(stripe);
+        }
+
+        let remainder = stripes.remainder();
+        self.buffer[..remainder.len()].copy_from_slice(remainder);
+        self.buffer_len = remainder.len();
+    }
+
+    pub fn digest(&self) -> u64 {
+        let mut hash = if self.total_len >= STRIPE_LEN as u64 {
+            let [v1, v2, v3, v4] = self.accumulators;
+            let mut hash = v1
+                .rotate_left(1)
+                .wrapping_add(v2.rotate_left(7))
+                .wrapping_add(v3.rotate_left(12))
+                .wrapping_add(v4.rotate_left(18));
+            for v in self.accumulators {
+                hash = Self::merge_accumulator(hash, v);
+            }
+            hash
+        } else {
+            self.seed.wrapping_add(PRIME64_5)
+        };
+
+        hash = hash.wrapping_add(self.total_len);
+
+        let mut remaining = &self.buffer[..self.buffer_len];
+        while remaining.len() >= 8 {
+            let lane = u64::from_le_bytes(remaining[..8].try_into().unwrap());
+            hash ^= Self::round(0, lane);
+            hash = hash
+                .rotate_left(27)
+                .wrapping_mul(PRIME64_1)
+                .wrapping_add(PRIME64_4);
+            remaining = &remaining[8..];
+        }
+
+        if remaining.len() >= 4 {
+            let lane = u32::from_le_bytes(remaining[..4].try_into().unwrap()) as u64;
+            hash ^= lane.wrapping_mul(PRIME64_1);
+            hash = hash
+                .rotate_left(23)
+                .wrapping_mul(PRIME64_2)
+                .wrapping_add(PRIME64_3);
+            remaining = &remaining[4..];
+        }
+
+        for byte in remaining {
+            hash ^= (*byte as u64).wrapping_mul(PRIME64_5);
+            hash = hash.rotate_left(11).wrapping_mul(PRIME64_1);
+        }
+
+        hash ^= hash >> 33;
+        hash = hash.wrapping_mul(PRIME64_2);
+        hash ^= hash >> 29;
+        hash = hash.wrapping_mul(PRIME64_3);
+        hash ^= hash >> 32;
+        hash
+    }
+
+    fn process_stripe(&mut self, stripe: &[u8]) {
+        for (accumulator, lane) in self.accumulators.iter_mut().zip(stripe.chunks_exact(8)) {
+            let lane = u64::from_le_bytes(lane.try_into().unwrap());
+            *accumulator = Self::round(*accumulator, lane);
+        }
+    }
+
+    fn round(accumulator: u64, lane: u64) -> u64 {
+        accumulator
+            .wrapping_add(lane.wrapping_mul(PRIME64_2))
+            .rotate_left(31)
+            .wrapping_mul(PRIME64_1)
+    }
+
+    fn merge_accumulator(hash: u64, accumulator: u64) -> u64 {
+        (hash ^ Self::round(0, accumulator))
+            .wrapping_mul(PRIME64_1)
+            .wrapping_add(PRIME64_4)
+    }
+}
+
+pub fn xxh64(bytes: &[u8], seed: u64) -> u64 {
+    let mut hasher = Xxh64::new(seed);
+    hasher.update(bytes);
+    hasher.digest()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn known_hashes() {
+        assert_eq!(xxh64(b"", 0), 0xEF46DB3751D8E999);
+        assert_eq!(xxh64(b"a", 0), 0xD24EC4F1A98C6E5B);
+        assert_eq!(xxh64(b"abc", 0), 0x44BC2CF5AD770999);
+        assert_eq!(
+            xxh64(b"Nobody inspects the spammish repetition", 0),
+            0xFBCEA83C8A378BF1
+        );
+    }
+
+    #[test]
+    fn streaming_matches_one_shot() {
+        let bytes = (0..1000u32)
+            .map(|i| (i * 7 % 251) as u8)
+            .collect::<Vec<_>>();
+        let expected = xxh64(&bytes, 0);
+
+        for chunk_size in [1, 5, 31, 32, 33, 100] {
+            let mut hasher = Xxh64::new(0);
+            for chunk in bytes.chunks(chunk_size) {
+                hasher.update(chunk);
+            }
+            assert_eq!(hasher.digest(), expected, "chunk size {}", chunk_size);
+        }
+    }
+}
@@ -1,14 +1,19 @@
 use std::{
-    io::Write,
+    fs,
+    io::{self, Read, Write},
+    path::{Path, PathBuf},
     process::{Command, Stdio},
 };
 
-use zstd::{Res, Zstd};
+use zstd::{Dictionary, Error, PushDecoder, Zstd, ZstdDecoder, DEFAULT_LEVEL, MAX_LEVEL};
 
-fn compress_file(input_file_content: &[u8]) -> Res<Vec<u8>> {
+type Res<T> = Result<T, Box<dyn std::error::Error>>;
+
+fn compress_file(input_file_content: &[u8], args: &[&str]) -> Res<Vec<u8>> {
     let mut zstd = Command::new("zstd")
         .stdin(Stdio::piped())
         .stdout(Stdio::piped())
+        .args(args)
         .args(["-e", "-"])
         .spawn()?;
 
@@ -23,13 +28,154 @@ fn compress_file(input_file_content: &[u8]) -> Res<Vec<u8>> {
     Ok(output.stdout)
 }
 
+fn decompress_with_reference(compressed: &[u8]) -> Res<Vec<u8>> {
+    let mut zstd = Command::new("zstd")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .args(["-d", "-c", "-"])
+        .spawn()?;
+
+    let mut stdin = zstd.stdin.take().unwrap();
+    stdin.write_all(compressed)?;
+    drop(stdin);
+
+    let output = zstd.wait_with_output()?;
+    if !output.status.success() {
+        return Err(output.status.to_string().into());
+    }
+    Ok(output.stdout)
+}
+
 fn compression_test(expected: &[u8]) -> Res<()> {
-    let compressed = compress_file(expected)?;
-    let actual = Zstd::from_bytes(compressed)?.decode();
+    compression_test_with_args(expected, &[])
+}
+
+fn compression_test_with_args(expected: &[u8], args: &[&str]) -> Res<()> {
+    let compressed = compress_file(expected, args)?;
+    let actual = Zstd::from_bytes(compressed.clone())?.decode()?;
+    assert_eq!(actual, expected);
+
+    let actual = stream_decode(ZstdDecoder::new(ChunkedReader(&compressed)))?;
+    assert_eq!(actual, expected);
+
+    let actual = push_decode(PushDecoder::new(), &compressed)?;
     assert_eq!(actual, expected);
     Ok(())
 }
 
+/// Feeds `compressed` to `decoder` in chunks of varying sizes, so that every
+/// kind of structure ends up split across chunks.
+fn push_decode(mut decoder: PushDecoder, mut compressed: &[u8]) -> zstd::Res<Vec<u8>> {
+    let mut output = vec![];
+    for chunk_size in [1, 3, 7, 64, 1000].into_iter().cycle() {
+        if compressed.is_empty() {
+            break;
+        }
+        let (chunk, rest) = compressed.split_at(chunk_size.min(compressed.len()));
+        decoder.feed(chunk)?;
+        output.extend(decoder.take_output());
+        compressed = rest;
+    }
+    decoder.finish()?;
+    Ok(output)
+}
+
+/// Reader returning at most 1000 bytes at a time, so that headers and blocks
+/// are split across reads.
+struct ChunkedReader<'a>(&'a [u8]);
+
+impl Read for ChunkedReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = buf.len().min(self.0.len()).min(1000);
+        buf[..n].copy_from_slice(&self.0[..n]);
+        self.0 = &self.0[n..];
+        Ok(n)
+    }
+}
+
+/// Reads all the output of `decoder` through a small buffer.
+fn stream_decode(mut decoder: impl Read) -> io::Result<Vec<u8>> {
+    let mut output = vec![];
+    let mut buf = [0; 777];
+    loop {
+        let n = decoder.read(&mut buf)?;
+        if n == 0 {
+            return Ok(output);
+        }
+        output.extend_from_slice(&buf[..n]);
+    }
+}
+
+/// Xorshift generator, always starting from the same seed so that the tests
+/// are reproducible.
+struct TestRng(u64);
+
+impl TestRng {
+    fn new() -> Self {
+        Self(0x2545F4914F6CDD1D)
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+}
+
+/// Pseudo-random text made of common words, compressible enough for the
+/// reference encoder to use Huffman-coded literals.
+fn lorem_ipsum(len: usize) -> Vec<u8> {
+    const WORDS: [&str; 16] = [
+        "lorem",
+        "ipsum",
+        "dolor",
+        "sit",
+        "amet",
+        "consectetur",
+        "adipiscing",
+        "elit",
+        "sed",
+        "do",
+        "eiusmod",
+        "tempor",
+        "incididunt",
+        "ut",
+        "labore",
+        "magna",
+    ];
+
+    let mut rng = TestRng::new();
+    let mut text = Vec::with_capacity(len + 16);
+    while text.len() < len {
+        let state = rng.next_u64();
+        text.extend_from_slice(WORDS[(state % 16) as usize].as_bytes());
+        text.push(if state.is_multiple_of(11) {
+            b'\n'
+        } else {
+            b' '
+        });
+    }
+    text.truncate(len);
+    text
+}
+
+/// Pseudo-random bytes below `max_symbol`, small symbol values make the
+/// reference encoder describe Huffman weights directly.
+fn small_symbols(len: usize, max_symbol: u64) -> Vec<u8> {
+    let mut rng = TestRng::new();
+    (0..len)
+        .map(|_| {
+            let state = rng.next_u64();
+            if state.is_multiple_of(5) {
+                0
+            } else {
+                ((state >> 3) % max_symbol) as u8
+            }
+        })
+        .collect()
+}
+
 #[test]
 fn hello_world() -> Res<()> {
     let expected = b"hello world!";
@@ -41,3 +187,556 @@ fn thousand_a() -> Res<()> {
     let expected = b"a".repeat(1000);
     compression_test(&expected)
 }
+
+#[test]
+fn huffman_literals_one_stream() -> Res<()> {
+    let expected = small_symbols(200, 8);
+    compression_test(&expected)
+}
+
+#[test]
+fn huffman_literals_four_streams() -> Res<()> {
+    let expected = small_symbols(3000, 16);
+    compression_test(&expected)
+}
+
+#[test]
+fn huffman_fse_compressed_weights() -> Res<()> {
+    let expected = lorem_ipsum(200);
+    compression_test(&expected)
+}
+
+#[test]
+fn fse_compressed_sequences() -> Res<()> {
+    let expected = lorem_ipsum(3000);
+    compression_test(&expected)
+}
+
+#[test]
+fn fse_compressed_sequences_max_level() -> Res<()> {
+    let expected = small_symbols(5000, 8);
+    compression_test_with_args(&expected, &["-19"])
+}
+
+#[test]
+fn multiple_blocks() -> Res<()> {
+    let expected = small_symbols(300_000, 64);
+    compression_test(&expected)
+}
+
+#[test]
+fn multiple_blocks_max_level() -> Res<()> {
+    let expected = small_symbols(300_000, 64);
+    compression_test_with_args(&expected, &["-19"])
+}
+
+#[test]
+fn repeated_offsets_without_literals() -> Res<()> {
+    let expected = lorem_ipsum(1500);
+    compression_test(&expected)
+}
+
+#[test]
+fn corrupted_checksum() -> Res<()> {
+    let expected = lorem_ipsum(1000);
+    let mut compressed = compress_file(&expected, &["--check"])?;
+    let checksum_index = compressed.len() - 1;
+    compressed[checksum_index] ^= 1;
+
+    let error = Zstd::from_bytes(compressed.clone())?.decode().unwrap_err();
+    assert!(matches!(error, Error::ChecksumMismatch { .. }));
+
+    let actual = Zstd::from_bytes(compressed)?
+        .verify_checksums(false)
+        .decode()?;
+    assert_eq!(actual, expected);
+    Ok(())
+}
+
+#[test]
+fn skippable_frames() -> Res<()> {
+    let expected = lorem_ipsum(1000);
+
+    let mut bytes = vec![];
+    bytes.extend_from_slice(&0x184D2A50u32.to_le_bytes());
+    bytes.extend_from_slice(&3u32.to_le_bytes());
+    bytes.extend_from_slice(b"abc");
+    bytes.extend(compress_file(&expected[..500], &[])?);
+    bytes.extend(compress_file(&expected[500..], &[])?);
+    bytes.extend_from_slice(&0x184D2A5Fu32.to_le_bytes());
+    bytes.extend_from_slice(&0u32.to_le_bytes());
+
+    let zstd = Zstd::from_bytes(bytes)?;
+    let skippable_frames = zstd
+        .skippable_frames()
+        .iter()
+        .map(|frame| {
+            let user_data = frame.user_data().to_vec();
+            (frame.index(), frame.magic_variant(), user_data)
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(skippable_frames, [(0, 0, b"abc".to_vec()), (3, 15, vec![])]);
+
+    assert_eq!(zstd.decode()?, expected);
+    Ok(())
+}
+
+/// Trains a dictionary with the reference implementation on chunks of text
+/// and returns its path.
+fn train_dictionary(name: &str) -> Res<PathBuf> {
+    let dir = std::env::temp_dir().join(format!("zstd-{}-{}", name, std::process::id()));
+    fs::create_dir_all(&dir)?;
+
+    let text = lorem_ipsum(200_000);
+    let mut samples = vec![];
+    for (i, chunk) in text.chunks(2000).enumerate() {
+        let path = dir.join(format!("sample{}", i));
+        fs::write(&path, chunk)?;
+        samples.push(path);
+    }
+
+    let dictionary_path = dir.join("dictionary");
+    let status = Command::new("zstd")
+        .args(["-q", "-f", "--train", "--maxdict=4096", "-o"])
+        .arg(&dictionary_path)
+        .args(&samples)
+        .status()?;
+    if !status.success() {
+        return Err(status.to_string().into());
+    }
+    Ok(dictionary_path)
+}
+
+#[test]
+fn dictionary() -> Res<()> {
+    let dictionary_path = train_dictionary("dictionary")?;
+    let dictionary_bytes = fs::read(&dictionary_path)?;
+
+    let expected = lorem_ipsum(3000);
+    let compressed = compress_file(&expected, &["-D", dictionary_path.to_str().unwrap()])?;
+
+    let dictionary = Dictionary::from_bytes(&dictionary_bytes)?;
+    let actual = Zstd::from_bytes(compressed.clone())?
+        .with_dictionary(dictionary)
+        .decode()?;
+    assert_eq!(actual, expected);
+
+    let error = Zstd::from_bytes(compressed)?.decode().unwrap_err();
+    assert!(matches!(error, Error::MissingDictionary(_)));
+    Ok(())
+}
+
+#[test]
+fn raw_content_dictionary() -> Res<()> {
+    let dir = std::env::temp_dir().join(format!("zstd-raw-dictionary-{}", std::process::id()));
+    fs::create_dir_all(&dir)?;
+    let dictionary_path = dir.join("dictionary");
+    let dictionary_content = lorem_ipsum(5000);
+    fs::write(&dictionary_path, &dictionary_content)?;
+
+    let expected = lorem_ipsum(3000);
+    let compressed = compress_file(&expected, &["-D", dictionary_path.to_str().unwrap()])?;
+
+    let dictionary = Dictionary::from_raw_content(dictionary_content);
+    let actual = Zstd::from_bytes(compressed)?
+        .with_dictionary(dictionary)
+        .decode()?;
+    assert_eq!(actual, expected);
+    Ok(())
+}
+
+#[test]
+fn reference_prefix() -> Res<()> {
+    let dir = std::env::temp_dir().join(format!("zstd-prefix-{}", std::process::id()));
+    fs::create_dir_all(&dir)?;
+    let reference_path = dir.join("reference");
+    let reference = lorem_ipsum(200_000);
+    fs::write(&reference_path, &reference)?;
+
+    let mut expected = reference.clone();
+    expected[1000..1010].copy_from_slice(b"0123456789");
+    expected.extend_from_slice(b"appended at the end");
+    let patch_from = format!("--patch-from={}", reference_path.to_str().unwrap());
+    let stream_size = format!("--stream-size={}", expected.len());
+    let compressed = compress_file(&expected, &[&patch_from, &stream_size])?;
+
+    let actual = Zstd::from_bytes(compressed.clone())?
+        .with_prefix(reference)
+        .decode()?;
+    assert_eq!(actual, expected);
+
+    assert!(Zstd::from_bytes(compressed)?.decode().is_err());
+    Ok(())
+}
+
+#[test]
+fn invalid_frame_headers() -> Res<()> {
+    let compressed = compress_file(b"hello world", &[])?;
+
+    let mut invalid_magic = compressed.clone();
+    invalid_magic[0] = 0;
+    assert!(matches!(
+        Zstd::from_bytes(invalid_magic),
+        Err(Error::InvalidMagic(_))
+    ));
+
+    let mut reserved_bit = compressed.clone();
+    reserved_bit[4] |= 1 << 3;
+    assert!(matches!(
+        Zstd::from_bytes(reserved_bit),
+        Err(Error::ReservedBitSet(_))
+    ));
+
+    // Window descriptor with the maximum exponent and mantissa.
+    let large_window = [0x28, 0xB5, 0x2F, 0xFD, 0x00, 0xFF, 0x01, 0x00, 0x00];
+    assert!(matches!(
+        Zstd::from_bytes(large_window.to_vec()),
+        Err(Error::WindowTooLarge(_))
+    ));
+
+    // Single segment frame, whose window is its huge content size.
+    let mut large_content = vec![0x28, 0xB5, 0x2F, 0xFD, 0xE0];
+    large_content.extend(u64::MAX.to_le_bytes());
+    large_content.extend([0x01, 0x00, 0x00]);
+    assert!(matches!(
+        Zstd::from_bytes(large_content.clone()),
+        Err(Error::WindowTooLarge(_))
+    ));
+    assert!(stream_decode(ZstdDecoder::new(&large_content[..])).is_err());
+
+    assert!(matches!(
+        Zstd::from_bytes(compressed[..6].to_vec()),
+        Err(Error::Truncated(_))
+    ));
+    Ok(())
+}
+
+/// Decodes `compressed` and every prefix of it, then copies of it with
+/// pseudo-random bytes replaced. Decoding may fail but must not panic.
+fn malformed_input_test(compressed: &[u8], mutations: usize) {
+    for len in 0..compressed.len() {
+        let _ = Zstd::from_bytes(compressed[..len].to_vec()).and_then(Zstd::decode);
+    }
+
+    let mut rng = TestRng::new();
+    for _ in 0..mutations {
+        let mut corrupted = compressed.to_vec();
+        for _ in 0..1 + rng.next_u64() % 3 {
+            let state = rng.next_u64();
+            let index = (state >> 8) as usize % corrupted.len();
+            corrupted[index] = (state >> 40) as u8;
+        }
+        let _ = Zstd::from_bytes(corrupted).and_then(Zstd::decode);
+    }
+}
+
+#[test]
+fn malformed_input() -> Res<()> {
+    let inputs = [
+        compress_file(b"hello world", &[])?,
+        compress_file(&[b'a'; 1000], &[])?,
+        compress_file(&small_symbols(200, 8), &[])?,
+        compress_file(&small_symbols(3000, 16), &["--check"])?,
+        compress_file(&lorem_ipsum(200), &[])?,
+        compress_file(&lorem_ipsum(3000), &["-19"])?,
+        compress_file(&lorem_ipsum(1500), &["--no-check"])?,
+    ];
+    for compressed in inputs {
+        malformed_input_test(&compressed, 2000);
+    }
+    Ok(())
+}
+
+#[test]
+fn malformed_dictionary() -> Res<()> {
+    let dictionary = fs::read(train_dictionary("malformed")?)?;
+    // Only the header and entropy tables are parsed, the content is not.
+    for len in 0..512 {
+        let _ = Dictionary::from_bytes(&dictionary[..len]);
+    }
+
+    let mut rng = TestRng::new();
+    for _ in 0..1000 {
+        let state = rng.next_u64();
+        let mut corrupted = dictionary.clone();
+        let index = 8 + (state >> 8) as usize % 256;
+        corrupted[index] = (state >> 40) as u8;
+        let _ = Dictionary::from_bytes(&corrupted);
+    }
+    Ok(())
+}
+
+#[test]
+fn fuzz_seed_corpus() -> Res<()> {
+    let corpus = Path::new(env!("CARGO_MANIFEST_DIR")).join("fuzz/corpus/decode");
+    for entry in fs::read_dir(corpus)? {
+        let path = entry?.path();
+        let compressed = fs::read(&path)?;
+
+        let output = Command::new("zstd")
+            .args(["-d", "-c", "-q"])
+            .arg(&path)
+            .output()?;
+        assert!(output.status.success(), "{:?}", path);
+
+        let actual = Zstd::from_bytes(compressed)?.decode()?;
+        assert_eq!(actual, output.stdout, "{:?}", path);
+    }
+    Ok(())
+}
+
+#[test]
+fn streaming_decoder_small_window() -> Res<()> {
+    let expected = small_symbols(300_000, 64);
+    compression_test_with_args(&expected, &["--zstd=windowLog=10"])
+}
+
+#[test]
+fn streaming_decoder_frames() -> Res<()> {
+    let mut compressed = compress_file(b"first frame, ", &[])?;
+    compressed.extend_from_slice(&0x184D2A5Fu32.to_le_bytes());
+    compressed.extend_from_slice(&3u32.to_le_bytes());
+    compressed.extend_from_slice(b"abc");
+    compressed.extend(compress_file(&lorem_ipsum(3000), &["--check"])?);
+
+    let mut expected = b"first frame, ".to_vec();
+    expected.extend(lorem_ipsum(3000));
+    let actual = stream_decode(ZstdDecoder::new(ChunkedReader(&compressed)))?;
+    assert_eq!(actual, expected);
+
+    let error = stream_decode(ZstdDecoder::new(&compressed[..compressed.len() - 2])).unwrap_err();
+    assert_eq!(error.kind(), io::ErrorKind::UnexpectedEof);
+
+    let checksum_index = compressed.len() - 1;
+    compressed[checksum_index] ^= 1;
+    let error = stream_decode(ZstdDecoder::new(&compressed[..])).unwrap_err();
+    assert!(matches!(
+        error.get_ref().and_then(|e| e.downcast_ref()),
+        Some(Error::ChecksumMismatch { .. })
+    ));
+
+    let actual = stream_decode(ZstdDecoder::new(&compressed[..]).verify_checksums(false))?;
+    assert_eq!(actual, expected);
+    Ok(())
+}
+
+#[test]
+fn push_decoder_frames() -> Res<()> {
+    let mut compressed = compress_file(b"first frame, ", &[])?;
+    compressed.extend_from_slice(&0x184D2A5Fu32.to_le_bytes());
+    compressed.extend_from_slice(&3u32.to_le_bytes());
+    compressed.extend_from_slice(b"abc");
+    compressed.extend(compress_file(&lorem_ipsum(3000), &["--check"])?);
+
+    let mut expected = b"first frame, ".to_vec();
+    expected.extend(lorem_ipsum(3000));
+    assert_eq!(push_decode(PushDecoder::new(), &compressed)?, expected);
+
+    let mut decoder = PushDecoder::new();
+    decoder.feed(&compressed)?;
+    assert_eq!(decoder.take_output(), expected);
+    assert!(decoder.take_output().is_empty());
+    decoder.finish()?;
+
+    for len in [1, 4, 9, 20, compressed.len() - 2] {
+        let error = push_decode(PushDecoder::new(), &compressed[..len]).unwrap_err();
+        assert!(matches!(error, Error::Truncated(_)), "{:?}", error);
+    }
+
+    let checksum_index = compressed.len() - 1;
+    compressed[checksum_index] ^= 1;
+    let error = push_decode(PushDecoder::new(), &compressed).unwrap_err();
+    assert!(matches!(error, Error::ChecksumMismatch { .. }));
+
+    let decoder = PushDecoder::new().verify_checksums(false);
+    assert_eq!(push_decode(decoder, &compressed)?, expected);
+    Ok(())
+}
+
+#[test]
+fn push_decoder_bytes_needed() -> Res<()> {
+    let expected = lorem_ipsum(300_000);
+    let compressed = compress_file(&expected, &["--check"])?;
+
+    let mut decoder = PushDecoder::new();
+    let mut output = vec![];
+    let mut position = 0;
+    while position < compressed.len() {
+        let end = position + decoder.bytes_needed();
+        assert!(end <= compressed.len());
+        decoder.feed(&compressed[position..end])?;
+        output.extend(decoder.take_output());
+        position = end;
+    }
+    decoder.finish()?;
+    assert_eq!(output, expected);
+    Ok(())
+}
+
+/// Compresses `input` with our encoder and checks that both our decoder and
+/// the reference one restore it. Returns the compressed size.
+fn encoding_test(input: &[u8]) -> Res<usize> {
+    encoding_test_with_level(input, DEFAULT_LEVEL)
+}
+
+fn encoding_test_with_level(input: &[u8], level: u8) -> Res<usize> {
+    let compressed = Zstd::encode_with_level(input.to_vec(), level)?;
+    assert_eq!(Zstd::from_bytes(compressed.clone())?.decode()?, input);
+    assert_eq!(decompress_with_reference(&compressed)?, input);
+    Ok(compressed.len())
+}
+
+#[test]
+fn encode_content_sizes() -> Res<()> {
+    encoding_test(b"")?;
+    encoding_test(b"Hello, world!")?;
+    encoding_test(&lorem_ipsum(255))?;
+    encoding_test(&lorem_ipsum(256))?;
+    encoding_test(&lorem_ipsum(65791))?;
+    encoding_test(&lorem_ipsum(65792))?;
+    // Several blocks in a single segment frame, then in a frame with a
+    // window descriptor.
+    encoding_test(&lorem_ipsum(300_000))?;
+    encoding_test(&lorem_ipsum(600_000))?;
+    Ok(())
+}
+
+#[test]
+fn encode_rle_blocks() -> Res<()> {
+    let compressed_len = encoding_test(&[b'a'; 1000])?;
+    assert!(compressed_len < 20, "{}", compressed_len);
+
+    let compressed_len = encoding_test(&vec![0; 1_000_000])?;
+    assert!(compressed_len < 50, "{}", compressed_len);
+
+    // Only the blocks made of a single byte are RLE blocks.
+    let mut input = lorem_ipsum(1 << 17);
+    input.extend([b'.'; 300_000]);
+    let compressed_len = encoding_test(&input)?;
+    assert!(compressed_len < (1 << 17) + 50, "{}", compressed_len);
+
+    // Runs inside the other blocks are matches.
+    let mut input = lorem_ipsum(1000);
+    input.extend([b'-'; 15]);
+    input.extend(lorem_ipsum(1000));
+    input.extend([b'='; 5000]);
+    input.extend(lorem_ipsum(1000));
+    input.extend([b'.'; 200_000]);
+    let compressed_len = encoding_test(&input)?;
+    assert!(compressed_len < 3100, "{}", compressed_len);
+    Ok(())
+}
+
+#[test]
+fn encode_compressed_blocks() -> Res<()> {
+    let input = lorem_ipsum(1_000_000);
+    let compressed_len = encoding_test(&input)?;
+    assert!(compressed_len < input.len() / 2, "{}", compressed_len);
+
+    // Incompressible blocks are stored raw.
+    let input = small_symbols(300_000, 256);
+    let compressed_len = encoding_test(&input)?;
+    assert!(compressed_len < input.len() + 100, "{}", compressed_len);
+
+    encoding_test(&small_symbols(300_000, 4))?;
+    encoding_test(b"abcabcabcabcabcabcabcabcabcabcabc")?;
+    Ok(())
+}
+
+#[test]
+fn encode_repeated_offsets() -> Res<()> {
+    // Records differing by a few bytes, whose matches alternate between the
+    // same offsets, with and without literals in between.
+    let record = lorem_ipsum(300);
+    let mut input = vec![];
+    for i in 0..2000u32 {
+        input.extend_from_slice(&record[..(i % 7) as usize * 40]);
+        input.extend(i.to_le_bytes());
+        input.extend_from_slice(&record);
+    }
+    let compressed_len = encoding_test(&input)?;
+    assert!(compressed_len < input.len() / 20, "{}", compressed_len);
+    Ok(())
+}
+
+#[test]
+fn encode_matches_across_blocks() -> Res<()> {
+    // The second half repeats the first one, 300 KB back.
+    let mut input = small_symbols(300_000, 256);
+    input.extend_from_within(..);
+    let compressed_len = encoding_test(&input)?;
+    assert!(compressed_len < 310_000, "{}", compressed_len);
+    Ok(())
+}
+
+#[test]
+fn encode_sequence_tables() -> Res<()> {
+    // Records with a single changing byte give sequences of identical codes,
+    // written with RLE tables.
+    let record = lorem_ipsum(100);
+    let mut input = vec![];
+    for i in 0..5000u32 {
+        input.push(i as u8);
+        input.extend_from_slice(&record);
+    }
+    let compressed_len = encoding_test(&input)?;
+    assert!(compressed_len < input.len() / 50, "{}", compressed_len);
+
+    // Few sequences keep the predefined tables, many are worth tables
+    // fitted to their codes.
+    for len in [50, 1000, 20_000] {
+        encoding_test(&lorem_ipsum(len))?;
+    }
+    let compressed_len = encoding_test(&lorem_ipsum(1_000_000))?;
+    assert!(compressed_len < 250_000, "{}", compressed_len);
+    Ok(())
+}
+
+#[test]
+fn encode_huffman_literals() -> Res<()> {
+    // Few distinct bytes without matches, compressible by Huffman coding only.
+    let input = small_symbols(100_000, 16);
+    let compressed_len = encoding_test(&input)?;
+    assert!(compressed_len < input.len() * 6 / 10, "{}", compressed_len);
+
+    // Literals up to 255, whose weights can only be written FSE-compressed.
+    let input = input.iter().map(|byte| byte * 16 + 15).collect::<Vec<_>>();
+    let compressed_len = encoding_test(&input)?;
+    assert!(compressed_len < input.len() * 6 / 10, "{}", compressed_len);
+
+    // Short blocks of literals are written in a single stream.
+    encoding_test(&small_symbols(200, 8))?;
+    Ok(())
+}
+
+#[test]
+fn encode_levels() -> Res<()> {
+    let mut input = lorem_ipsum(60_000);
+    input.extend(small_symbols(20_000, 64));
+    input.extend_from_within(10_000..50_000);
+
+    let mut compressed_lens = vec![];
+    for level in 1..=MAX_LEVEL {
+        compressed_lens.push(encoding_test_with_level(&input, level)?);
+        encoding_test_with_level(b"abcabcabcabc, abcabcabcabc", level)?;
+    }
+    // Each strategy finds better matches than the faster ones.
+    let [fast, double_fast, greedy, lazy, lazy2] = [1, 3, 5, 6, 8].map(|l| compressed_lens[l - 1]);
+    assert!(double_fast < fast, "{:?}", compressed_lens);
+    assert!(greedy < double_fast, "{:?}", compressed_lens);
+    assert!(lazy < greedy, "{:?}", compressed_lens);
+    assert!(lazy2 < lazy, "{:?}", compressed_lens);
+    assert!(
+        compressed_lens[MAX_LEVEL as usize - 1] <= lazy2,
+        "{:?}",
+        compressed_lens
+    );
+
+    // Level 0 is the default level, the levels above the maximum are the
+    // maximum.
+    let encode = |level| Zstd::encode_with_level(input.clone(), level);
+    assert_eq!(encode(0)?, Zstd::encode(input.clone())?);
+    assert_eq!(encode(0)?, encode(DEFAULT_LEVEL)?);
+    assert_eq!(encode(MAX_LEVEL + 1)?, encode(MAX_LEVEL)?);
+    Ok(())
+}